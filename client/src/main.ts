@@ -7,15 +7,35 @@ import {
   establishPayer,
   checkProgram,
   exchangeSOLByCLASH,
+  buyExactCLASH,
+  exchangeSPLByCLASH,
   getCLASHAuthorityInfo,
   loadExchangerInfoFromFile,
   initializeICO,
   getCurrentPayer,
   terminateICO,
+  updateSaleConfig,
+  pauseSale,
+  resumeSale,
+  finalizeSale,
+  refundPurchase,
+  claimTokens,
+  claimRebate,
+  commitFairLaunch,
+  placeBid,
+  revealBid,
+  settleBid,
+  setSaleId,
+  publishSOLPrice,
+  getPriceFeedPDA,
   confirmCLASHPayment,
   loadTokenId
 } from './program';
 
+import { LAMPORTS_PER_SOL, PublicKey } from '@solana/web3.js';
+
+import { createKeypairFromFile } from './utils';
+
 import fs from 'mz/fs';
 
 async function main(argv:any) {
@@ -42,6 +62,9 @@ async function main(argv:any) {
     return;
   }
 
+  // Selects the sale to operate on through `--sale <id>`
+  setSaleId(BigInt(argv.sale != undefined ? argv.sale : 0));
+
   // Load data to use
   let clashAuthorityInfo = await getCLASHAuthorityInfo();
   let exchangerInfo = await loadExchangerInfoFromFile("./dist/static_wallet.json");
@@ -51,14 +74,103 @@ async function main(argv:any) {
   }
 
   if (command ==="init") {
-    await initializeICO(clashAuthorityInfo, await getCurrentPayer());
+    let configFile = (argv._[1] != undefined) ? argv._[1] : "config.json";
+    await initializeICO(clashAuthorityInfo, await getCurrentPayer(), await loadSaleConfig(configFile));
+  }
+  else if (command === "update") {
+    let configFile = (argv._[1] != undefined) ? argv._[1] : "config.json";
+    await updateSaleConfig(await getCurrentPayer(), await loadSaleConfig(configFile));
+  }
+  else if (command === "price") {
+    if (argv._[1] == undefined) {
+      throw "Missing SOL/USD price to publish";
+    }
+    await publishSOLPrice(await getCurrentPayer(), parseFloat(argv._[1]));
+  }
+  else if (command === "pause") {
+    let authority = argv.authority != undefined ? await createKeypairFromFile(argv.authority) : await getCurrentPayer();
+    await pauseSale(authority);
+  }
+  else if (command === "resume") {
+    await resumeSale(await getCurrentPayer());
+  }
+  else if (command === "finalize") {
+    await finalizeSale(clashAuthorityInfo, await getCurrentPayer());
+  }
+  else if (command === "refund") {
+    await refundPurchase(exchangerInfo, argv.buyer != undefined ? new PublicKey(argv.buyer) : undefined);
+  }
+  else if (command === "claim") {
+    await claimTokens(exchangerInfo, argv.buyer != undefined ? new PublicKey(argv.buyer) : undefined);
+  }
+  else if (command === "rebate") {
+    await claimRebate(exchangerInfo, argv.buyer != undefined ? new PublicKey(argv.buyer) : undefined);
+  }
+  else if (command === "commit") {
+    if (argv._[1] == undefined) {
+      throw "Usage: commit <SOL amount>";
+    }
+    await commitFairLaunch(exchangerInfo, parseFloat(argv._[1]));
+  }
+  else if (command === "bid") {
+    if (argv._[1] == undefined || argv._[2] == undefined || argv.escrow == undefined) {
+      throw "Usage: bid <SOL price per CLASH> <CLASH base units> --escrow <SOL amount>";
+    }
+    let bid = {
+      priceLamports: BigInt(Math.round(parseFloat(argv._[1]) * LAMPORTS_PER_SOL)),
+      CLASHAmount: BigInt(argv._[2]),
+      salt: require('crypto').randomBytes(32)
+    };
+    await saveBid(argv.bid != undefined ? argv.bid : "bid.json", bid);
+    await placeBid(exchangerInfo, bid, parseFloat(argv.escrow));
+  }
+  else if (command === "reveal") {
+    await revealBid(exchangerInfo, await loadBid(argv.bid != undefined ? argv.bid : "bid.json"));
+  }
+  else if (command === "settle") {
+    await settleBid(clashAuthorityInfo, exchangerInfo, argv.buyer != undefined ? new PublicKey(argv.buyer) : undefined);
   }
   else if (command === "terminate") {
     await terminateICO(clashAuthorityInfo, await getCurrentPayer());
   }
   else if (command === "exchange") {
     let amount = (argv._[1] != undefined) ? parseFloat(argv._[1]) : 0.35;
-    await exchangeSOLByCLASH(clashAuthorityInfo, exchangerInfo, {SOLAmount: amount});
+    let saleConfig = await loadSaleConfig(argv.config != undefined ? argv.config : "config.json");
+    let priceAccount = saleConfig.priceFeed ? await getPriceFeedPDA(saleConfig.priceFeed.publisher) : saleConfig.pyth?.priceAccount;
+    await exchangeSOLByCLASH(clashAuthorityInfo, exchangerInfo, {
+      SOLAmount: amount,
+      priceAccount: priceAccount,
+      minCLASHOut: argv['min-out'] != undefined ? BigInt(argv['min-out']) : undefined,
+      expirySlots: argv['expiry-slots'],
+      allowlistProof: argv.proof != undefined ? await loadAllowlistProof(argv.proof) : undefined,
+      allowPartialFill: argv.partial === true
+    });
+  }
+  else if (command === "buy") {
+    if (argv._[1] == undefined || argv['max-sol'] == undefined) {
+      throw "Usage: buy <CLASH base units> --max-sol <SOL amount>";
+    }
+    let saleConfig = await loadSaleConfig(argv.config != undefined ? argv.config : "config.json");
+    let priceAccount = saleConfig.priceFeed ? await getPriceFeedPDA(saleConfig.priceFeed.publisher) : saleConfig.pyth?.priceAccount;
+    await buyExactCLASH(clashAuthorityInfo, exchangerInfo, {
+      CLASHAmount: BigInt(argv._[1]),
+      maxSOLAmount: parseFloat(argv['max-sol']),
+      priceAccount: priceAccount,
+      expirySlots: argv['expiry-slots'],
+      allowlistProof: argv.proof != undefined ? await loadAllowlistProof(argv.proof) : undefined
+    });
+  }
+  else if (command === "buy-spl") {
+    if (argv._[1] == undefined || argv._[2] == undefined) {
+      throw "Usage: buy-spl <quote mint> <quote base units>";
+    }
+    await exchangeSPLByCLASH(clashAuthorityInfo, exchangerInfo, {
+      quoteMint: new PublicKey(argv._[1]),
+      quoteAmount: BigInt(argv._[2]),
+      minCLASHOut: argv['min-out'] != undefined ? BigInt(argv['min-out']) : undefined,
+      expirySlots: argv['expiry-slots'],
+      allowlistProof: argv.proof != undefined ? await loadAllowlistProof(argv.proof) : undefined
+    });
   }
   else if (command === "confirm") {
     let amount = (argv._[1] != undefined) ? parseFloat(argv._[1]) : 0.35;
@@ -72,15 +184,35 @@ async function updateProgramConfig(configPath:string): Promise<void> {
   console.log("Configuring program with values from file: " + configPath);
 
   let configSample = `{
-  "target_file": "program-rust/src/config.rs.dist",
-  "output_file": "program-rust/src/config.rs",
   "clash_team_sol_wallet": "<clash authority wallet address to receive SOL>",
   "clash_token_id": "<clash token address on the Solana blockchain>",
   "clash_payment_authority": "<authority key to sign a payment realized by coinpayment>",
   "ico_freeze_duration_days": 30,
+  "pyth_price_account": "",
+  "pyth_max_age_slots": 25,
+  "pyth_max_age_seconds": 60,
+  "pyth_max_confidence_bps": 100,
+  "price_feed_publisher": "",
+  "price_feed_max_age_seconds": 300,
+  "accepted_quote_mints": [],
   "clash_usd_price": 0.035,
   "min_usd_price": "1.0",
-  "max_usd_price": "10000.0"
+  "max_usd_price": "10000.0",
+  "max_usd_per_wallet": "0",
+  "allowlist_root": "",
+  "start_timestamp": 0,
+  "end_timestamp": 0,
+  "extension_window_minutes": 0,
+  "guardian": "",
+  "soft_cap_usd": "0",
+  "hard_cap_usd": "0",
+  "price_rounds": [],
+  "bonding_curve": [],
+  "dutch_auction": null,
+  "fair_launch": null,
+  "batch_auction": null,
+  "vesting": null,
+  "tge_timestamp": 0
 }`
 
   if (!await fs.exists(configPath)) {
@@ -93,38 +225,132 @@ async function updateProgramConfig(configPath:string): Promise<void> {
   const fileString = await fs.readFile(configPath, {encoding: 'utf8'});
   const config = JSON.parse(fileString);
 
-  const targetContent = await fs.readFile(config.target_file, {encoding: 'utf8'});
+  if (config.clash_token_id != undefined) {
+    let token_id_content = `{
+  "token_id": "TOKEN_ID"
+}`.replace("TOKEN_ID", config.clash_token_id);
+
+    await fs.writeFile("token_id.json", token_id_content);
+  };
+
+  console.log("Configured client to use token: " + config.clash_token_id);
+}
+
+async function loadSaleConfig(configPath:string): Promise<any> {
+  console.log("Loading ICO sale parameters from file: " + configPath);
 
-  // Replace by pattern matching
-  let modifiedContent = targetContent.replace("#CLASH_SOL_WALLET", config.clash_team_sol_wallet);
-  modifiedContent = modifiedContent.replace("#CLASH_TOKEN_ID", config.clash_token_id);
-  modifiedContent = modifiedContent.replace("#CLASH_PAYMENT_AUTHORITY", config.clash_payment_authority);
-  modifiedContent = modifiedContent.replace("#CLASH_USD", config.clash_usd_price);
-  modifiedContent = modifiedContent.replace("#MIN_USD", config.min_usd_price);
-  modifiedContent = modifiedContent.replace("#MAX_USD", config.max_usd_price);
+  const fileString = await fs.readFile(configPath, {encoding: 'utf8'});
+  const config = JSON.parse(fileString);
 
   const axios = require('axios');
   const SOLtoUSDQuotationURI = "https://api.coingecko.com/api/v3/simple/price?ids=solana&vs_currencies=usd";
 
-  await axios.get(SOLtoUSDQuotationURI)
-  .then((response:any) => {
-    console.log("Current SOL/USD price: ", response.data.solana.usd);
-    modifiedContent = modifiedContent.replace("#SOL_USD", response.data.solana.usd);
-  })
-  .catch((error:any) => {
-    console.log(error);
-  });
+  const response = await axios.get(SOLtoUSDQuotationURI);
+  console.log("Current SOL/USD price: ", response.data.solana.usd);
 
-  if (config.clash_token_id != undefined) {
-    let token_id_content = `{
-  "token_id": "TOKEN_ID"
-}`.replace("TOKEN_ID", config.clash_token_id);
+  // Sale runs from now for `ico_freeze_duration_days` unless a window is configured
+  let now = Math.floor(Date.now() / 1000);
+  let startTimestamp = config.start_timestamp ? config.start_timestamp : now;
+  let endTimestamp = config.end_timestamp ? config.end_timestamp : startTimestamp + config.ico_freeze_duration_days * 24 * 60 * 60;
 
-    await fs.writeFile("token_id.json", token_id_content);
+  return {
+    CLASHUSD: parseFloat(config.clash_usd_price),
+    SOLUSD: parseFloat(response.data.solana.usd),
+    minUSD: parseFloat(config.min_usd_price),
+    maxUSD: parseFloat(config.max_usd_price),
+    maxUSDPerWallet: parseFloat(config.max_usd_per_wallet ?? "0"),
+    allowlistRoot: config.allowlist_root ? Buffer.from(config.allowlist_root, 'hex') : undefined,
+    startTimestamp: startTimestamp,
+    endTimestamp: endTimestamp,
+    extensionWindowSeconds: (config.extension_window_minutes ?? 0) * 60,
+    guardian: config.guardian ? new PublicKey(config.guardian) : undefined,
+    softCapUSD: parseFloat(config.soft_cap_usd ?? "0"),
+    hardCapUSD: parseFloat(config.hard_cap_usd ?? "0"),
+    SOLWallet: new PublicKey(config.clash_team_sol_wallet),
+    paymentAuthority: new PublicKey(config.clash_payment_authority),
+    pyth: config.pyth_price_account ? {
+      priceAccount: new PublicKey(config.pyth_price_account),
+      maxAgeSlots: config.pyth_max_age_slots,
+      maxAgeSeconds: config.pyth_max_age_seconds,
+      maxConfidenceBps: config.pyth_max_confidence_bps
+    } : undefined,
+    priceFeed: config.price_feed_publisher ? {
+      publisher: new PublicKey(config.price_feed_publisher),
+      maxAgeSeconds: config.price_feed_max_age_seconds
+    } : undefined,
+    quoteMints: (config.accepted_quote_mints ?? []).map((quoteMint: any) => ({
+      mint: new PublicKey(quoteMint.mint),
+      USDPrice: parseFloat(quoteMint.usd_price)
+    })),
+    rounds: (config.price_rounds ?? []).map((round: any) => ({
+      USDPrice: parseFloat(round.usd_price),
+      allocation: BigInt(round.allocation),
+      startTimestamp: round.start_timestamp,
+      endTimestamp: round.end_timestamp
+    })),
+    bondingCurve: (config.bonding_curve ?? []).map((point: any) => ({
+      CLASHSold: BigInt(point.clash_sold),
+      USDPrice: parseFloat(point.usd_price)
+    })),
+    dutchAuction: config.dutch_auction ? {
+      startUSDPrice: parseFloat(config.dutch_auction.start_usd_price),
+      floorUSDPrice: parseFloat(config.dutch_auction.floor_usd_price),
+      durationSeconds: config.dutch_auction.duration_minutes * 60,
+      stepSeconds: (config.dutch_auction.step_minutes ?? 0) * 60,
+      rebate: config.dutch_auction.rebate ?? false
+    } : undefined,
+    fairLaunch: config.fair_launch ? {
+      allocation: BigInt(config.fair_launch.allocation),
+      raiseSOL: parseFloat(config.fair_launch.raise_sol),
+      maxCommitSOL: parseFloat(config.fair_launch.max_commit_sol ?? "0")
+    } : undefined,
+    batchAuction: config.batch_auction ? {
+      supply: BigInt(config.batch_auction.supply),
+      minPriceSOL: parseFloat(config.batch_auction.min_price_sol),
+      tickSOL: parseFloat(config.batch_auction.tick_sol),
+      revealEndTimestamp: config.batch_auction.reveal_end_timestamp
+    } : undefined,
+    vesting: config.vesting ? {
+      TGETimestamp: config.vesting.tge_timestamp,
+      TGEUnlockPercent: parseFloat(config.vesting.tge_unlock_percent ?? "0"),
+      cliffSeconds: (config.vesting.cliff_days ?? 0) * 24 * 60 * 60,
+      durationSeconds: (config.vesting.duration_days ?? 0) * 24 * 60 * 60
+    } : undefined,
+    TGETimestamp: config.tge_timestamp ? config.tge_timestamp : undefined
   };
+}
+
+// Bids are kept in a file until they are revealed, losing the salt makes a bid unrevealable
+async function saveBid(bidPath:string, bid:any): Promise<void> {
+  const content = JSON.stringify({
+    price_lamports: bid.priceLamports.toString(),
+    quantity: bid.CLASHAmount.toString(),
+    salt: bid.salt.toString('hex')
+  }, null, 2);
+
+  await fs.writeFile(bidPath, content, {encoding: 'utf8'});
+  console.log("Bid saved to file: " + bidPath);
+}
+
+async function loadBid(bidPath:string): Promise<any> {
+  const fileString = await fs.readFile(bidPath, {encoding: 'utf8'});
+  const bid = JSON.parse(fileString);
 
-  await fs.writeFile(config.output_file, modifiedContent, {encoding: 'utf8'});
-  console.log("Generated updated configuration file: " + config.output_file);
+  return {
+    priceLamports: BigInt(bid.price_lamports),
+    CLASHAmount: BigInt(bid.quantity),
+    salt: Buffer.from(bid.salt, 'hex')
+  };
+}
+
+async function loadAllowlistProof(proofPath:string): Promise<any> {
+  const fileString = await fs.readFile(proofPath, {encoding: 'utf8'});
+  const proof = JSON.parse(fileString);
+
+  return {
+    allocationUSD: proof.allocation_usd != undefined ? parseFloat(proof.allocation_usd) : undefined,
+    proof: proof.proof.map((node: string) => Buffer.from(node, 'hex'))
+  };
 }
 
 var argv = require('minimist')(process.argv.slice(2));
@@ -3,6 +3,7 @@
  */
 
 import {
+  AccountMeta,
   Connection,
   Keypair,
   PublicKey,
@@ -17,13 +18,16 @@ import { TOKEN_PROGRAM_ID } from '@solana/spl-token';
 
 import fs from 'mz/fs';
 
+import { keccak_256 } from 'js-sha3';
+
 import {
   getRpcUrl,
   getPayer,
   createKeypairFromFile,
   findAssociatedTokenAddress,
   SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID,
-  SYSTEM_PROGRAM_ID
+  SYSTEM_PROGRAM_ID,
+  BorshWriter
 } from './utils';
 
 /**
@@ -43,6 +47,11 @@ const DEPLOY_PROGRAM_CMD = "`solana program deploy dist/program/clash_exchange_p
 */
 let CLASH_TOKEN_ACCOUNT:PublicKey;
 
+/**
+ * ID of the sale to operate on, each sale has its own PDA and token vault
+*/
+let SALE_ID:bigint = BigInt(0);
+
 /**
  * Connection to the network
  */
@@ -109,10 +118,57 @@ export async function getCurrentPayer() : Promise<Keypair> {
   return payer;
 }
 
+export function setSaleId(saleId: bigint) {
+  SALE_ID = saleId;
+}
+
+export async function getPriceFeedPDA(publisher: PublicKey) : Promise<PublicKey> {
+    let [priceFeedPDA, seed] = await PublicKey.findProgramAddress([
+      Buffer.from("genezys-fin", 'utf8'),
+      Buffer.from("clash-ico", 'utf8'),
+      Buffer.from("price-feed", 'utf8'),
+      publisher.toBuffer()
+    ], programId);
+
+    return priceFeedPDA;
+}
+
+export async function getBuyerRecordPDA(buyer: PublicKey) : Promise<PublicKey> {
+    let [programPDA, _] = await getProgramPDA();
+
+    let [buyerRecordPDA, seed] = await PublicKey.findProgramAddress([
+      Buffer.from("genezys-fin", 'utf8'),
+      Buffer.from("clash-ico", 'utf8'),
+      Buffer.from("buyer-record", 'utf8'),
+      programPDA.toBuffer(),
+      buyer.toBuffer()
+    ], programId);
+
+    return buyerRecordPDA;
+}
+
+export async function getVaultPDA() : Promise<PublicKey> {
+    let [programPDA, _] = await getProgramPDA();
+
+    let [vaultPDA, seed] = await PublicKey.findProgramAddress([
+      Buffer.from("genezys-fin", 'utf8'),
+      Buffer.from("clash-ico", 'utf8'),
+      Buffer.from("vault", 'utf8'),
+      programPDA.toBuffer()
+    ], programId);
+
+    return vaultPDA;
+}
+
 async function getProgramPDA() : Promise<[PublicKey, number]> {
+    let saleIdSeed = Buffer.alloc(8);
+    saleIdSeed.writeBigUInt64LE(SALE_ID);
+
     let [programPDA, seed] = await PublicKey.findProgramAddress([
       Buffer.from("genezys-fin", 'utf8'),
-      Buffer.from("clash-ico", 'utf8')
+      Buffer.from("clash-ico", 'utf8'),
+      saleIdSeed,
+      CLASH_TOKEN_ACCOUNT.toBuffer()
     ], programId);
 
     return [programPDA, seed]
@@ -162,7 +218,7 @@ export async function loadTokenId(): Promise<boolean> {
   return CLASH_TOKEN_ACCOUNT != undefined;
 }
 
-export async function initializeICO(clashAuthorityInfo: CLASHAuthorityInfo, initializer:Keypair) : Promise<void> {
+export async function initializeICO(clashAuthorityInfo: CLASHAuthorityInfo, initializer:Keypair, saleConfig: ICOSaleConfig) : Promise<void> {
   console.log(`Initializing ICO program.`)
 
   let [programPDA, seed] = await getProgramPDA();
@@ -173,8 +229,7 @@ export async function initializeICO(clashAuthorityInfo: CLASHAuthorityInfo, init
 
   let initializerATA = await findAssociatedTokenAddress(initializer.publicKey, CLASH_TOKEN_ACCOUNT);
 
-  let programData = Buffer.alloc(1);
-  programData.writeUInt8(0); // at 0: Instruction type
+  let programData = encodeSaleConfig(0, saleConfig);
 
   const instruction = new TransactionInstruction({
     keys: [
@@ -211,19 +266,221 @@ export async function initializeICO(clashAuthorityInfo: CLASHAuthorityInfo, init
   console.log("    Program ATA: ", clashAuthorityInfo.ATAWallet.toString());
 }
 
+export async function updateSaleConfig(initializer:Keypair, saleConfig: ICOSaleConfig) : Promise<void> {
+  console.log(`Updating ICO sale parameters.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+
+  let programData = encodeSaleConfig(4, saleConfig);
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      // Clash authority accounts
+      {pubkey: initializer.publicKey, isSigner: true, isWritable: false},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA holding the sale parameters
+      {pubkey: programPDA, isSigner: false, isWritable: true}
+    ],
+    programId,
+    data: programData
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [initializer]
+  );
+}
+
+/**
+ * Pauses purchases and payments, callable by the initializer or the sale guardian
+ */
+export async function pauseSale(authority: Keypair) : Promise<void> {
+  console.log(`Pausing ICO sale.`)
+
+  await sendPauseInstruction(8, authority);
+}
+
+/**
+ * Resumes a paused sale, callable by the initializer only
+ */
+export async function resumeSale(initializer: Keypair) : Promise<void> {
+  console.log(`Resuming ICO sale.`)
+
+  await sendPauseInstruction(9, initializer);
+}
+
+async function sendPauseInstruction(instructionType: number, authority: Keypair) : Promise<void> {
+  let [programPDA, seed] = await getProgramPDA();
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      // Initializer or guardian
+      {pubkey: authority.publicKey, isSigner: true, isWritable: false},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA holding the sale status
+      {pubkey: programPDA, isSigner: false, isWritable: true}
+    ],
+    programId,
+    data: encodeInstruction(instructionType).toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [authority]
+  );
+}
+
+export async function publishSOLPrice(publisher: Keypair, SOLUSD: number) : Promise<void> {
+  console.log(`Publishing SOL/USD price ${SOLUSD} to the program price feed.`)
+
+  let priceFeedPDA = await getPriceFeedPDA(publisher.publicKey);
+
+  let programData = new BorshWriter()
+    .u8(5) // Instruction type
+    .i64(toMicroUSD(SOLUSD)) // SOL price
+    .i32(-6) // Price exponent, price is published in micro USD
+    .toBuffer();
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      // Price publisher paying for the price feed account
+      {pubkey: publisher.publicKey, isSigner: true, isWritable: true},
+
+      // Price feed PDA
+      {pubkey: priceFeedPDA, isSigner: false, isWritable: true},
+
+      // Native system program account
+      {pubkey: SYSTEM_PROGRAM_ID, isSigner: false, isWritable: false}
+    ],
+    programId,
+    data: programData
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [publisher]
+  );
+}
+
 export async function exchangeSOLByCLASH(clashAuthorityInfo: CLASHAuthorityInfo, exchangerInfo: ExchangerInfo, exchangeInfo: ExchangeSOLByCLASHInfo
 ): Promise<void> {
 
   console.log(`Preparing to exchange SOL's by Clash tokens.`)
 
-  let programData = Buffer.alloc(9);
-  programData.writeUInt8(1); // at 0: Instruction type
-  programData.writeBigUInt64LE(BigInt(exchangeInfo.SOLAmount * LAMPORTS_PER_SOL), 1); // at 1: SOL amount
+  let writer = encodeInstruction(1)
+    .u8(3) // Exchange data version
+    .u64(BigInt(exchangeInfo.SOLAmount * LAMPORTS_PER_SOL)) // SOL amount
+    .u64(exchangeInfo.minCLASHOut ?? BigInt(0)); // Min CLASH base units to receive
+
+  await encodeExpirySlot(writer, exchangeInfo.expirySlots);
+  encodeAllowlistProof(writer, exchangeInfo.allowlistProof);
+
+  writer.bool(exchangeInfo.allowPartialFill ?? false); // Buy the tokens left instead of failing
+
+  await sendExchange(clashAuthorityInfo, exchangerInfo, priceAccountKeys(exchangeInfo.priceAccount), writer.toBuffer());
+}
+
+export async function buyExactCLASH(clashAuthorityInfo: CLASHAuthorityInfo, exchangerInfo: ExchangerInfo, buyInfo: BuyExactCLASHInfo
+): Promise<void> {
+
+  console.log(`Preparing to buy ${buyInfo.CLASHAmount} Clash base units paying at most ${buyInfo.maxSOLAmount} SOL.`)
+
+  let writer = encodeInstruction(6)
+    .u64(buyInfo.CLASHAmount) // CLASH base units to receive
+    .u64(BigInt(Math.round(buyInfo.maxSOLAmount * LAMPORTS_PER_SOL))); // Max lamports to pay
+
+  await encodeExpirySlot(writer, buyInfo.expirySlots);
+  encodeAllowlistProof(writer, buyInfo.allowlistProof);
+
+  await sendExchange(clashAuthorityInfo, exchangerInfo, priceAccountKeys(buyInfo.priceAccount), writer.toBuffer());
+}
+
+export async function exchangeSPLByCLASH(clashAuthorityInfo: CLASHAuthorityInfo, exchangerInfo: ExchangerInfo, exchangeInfo: ExchangeSPLByCLASHInfo
+): Promise<void> {
+
+  console.log(`Preparing to exchange ${exchangeInfo.quoteAmount} base units of ${exchangeInfo.quoteMint.toBase58()} by Clash tokens.`)
+
+  let writer = encodeInstruction(7)
+    .u64(exchangeInfo.quoteAmount) // Quote token base units to pay
+    .u64(exchangeInfo.minCLASHOut ?? BigInt(0)); // Min CLASH base units to receive
+
+  await encodeExpirySlot(writer, exchangeInfo.expirySlots);
+  encodeAllowlistProof(writer, exchangeInfo.allowlistProof);
+
+  // Quote tokens are paid from the exchanger ATA to the CLASH team ATA of the same mint
+  let treasuryATA = await findAssociatedTokenAddress(clashAuthorityInfo.SOLWallet, exchangeInfo.quoteMint);
+  let exchangerQuoteATA = await findAssociatedTokenAddress(exchangerInfo.SOLWallet.publicKey, exchangeInfo.quoteMint);
+
+  await sendExchange({...clashAuthorityInfo, SOLWallet: treasuryATA}, exchangerInfo, [
+    {pubkey: exchangerQuoteATA, isSigner: false, isWritable: true},
+    {pubkey: exchangeInfo.quoteMint, isSigner: false, isWritable: false},
+  ], writer.toBuffer());
+}
+
+/**
+ * SOL/USD price account, only when the sale reads its price from Pyth or a price feed
+ */
+function priceAccountKeys(priceAccount?: PublicKey): AccountMeta[] {
+  return priceAccount ? [{pubkey: priceAccount, isSigner: false, isWritable: false}] : [];
+}
 
+/**
+ * Encodes the optional slot after which an exchange is rejected
+ */
+async function encodeExpirySlot(writer: BorshWriter, expirySlots?: number): Promise<void> {
+  if (expirySlots != undefined) {
+    let currentSlot = await connection.getSlot();
+    writer.u8(1).u64(BigInt(currentSlot + expirySlots));
+  } else {
+    writer.u8(0);
+  }
+}
+
+/**
+ * Encodes the optional Merkle proof required by sales restricted to an allowlist
+ */
+function encodeAllowlistProof(writer: BorshWriter, allowlistProof?: AllowlistProof): void {
+  if (allowlistProof) {
+    let allocationUSD = allowlistProof.allocationUSD;
+
+    writer.u8(1);
+    if (allocationUSD != undefined) {
+      writer.u8(1).u64(toMicroUSD(allocationUSD));
+    } else {
+      writer.u8(0);
+    }
+
+    writer.u32(allowlistProof.proof.length);
+    for (let node of allowlistProof.proof) {
+      writer.bytes(node);
+    }
+  } else {
+    writer.u8(0);
+  }
+}
+
+/**
+ * Sends an exchange instruction, all exchange variants share the same accounts
+ * followed by the accounts specific to the payment currency
+ */
+async function sendExchange(clashAuthorityInfo: CLASHAuthorityInfo, exchangerInfo: ExchangerInfo, paymentKeys: AccountMeta[], programData: Buffer
+): Promise<void> {
   let [programPDA, seed] = await getProgramPDA();
 
   clashAuthorityInfo.ATAWallet = await findAssociatedTokenAddress(programPDA, CLASH_TOKEN_ACCOUNT);
 
+  let buyerRecordPDA = await getBuyerRecordPDA(exchangerInfo.SOLWallet.publicKey);
+  let vaultPDA = await getVaultPDA();
+
   const instruction = new TransactionInstruction({
     keys: [
       // User accounts
@@ -238,13 +495,22 @@ export async function exchangeSOLByCLASH(clashAuthorityInfo: CLASHAuthorityInfo,
 
       // Program account and PDA to sign
       {pubkey: programId, isSigner: false, isWritable: false},
-      {pubkey: programPDA, isSigner: false, isWritable: false},
+      {pubkey: programPDA, isSigner: false, isWritable: true},
 
       // Native system and token programs accounts
       {pubkey: SYSTEM_PROGRAM_ID, isSigner: false, isWritable: false},
       {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
       {pubkey: SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID, isSigner: false, isWritable: false},
       {pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false},
+
+      // Buyer purchases record PDA, created on the first purchase
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true},
+
+      // Vault PDA escrowing SOL until a soft cap sale is finalized
+      {pubkey: vaultPDA, isSigner: false, isWritable: true},
+
+      // SOL/USD price account or quote token accounts
+      ...paymentKeys,
     ],
     programId,
     data: programData
@@ -265,12 +531,14 @@ export async function confirmCLASHPayment(clashAuthorityInfo: CLASHAuthorityInfo
 
   clashAuthorityInfo.ATAWallet = await findAssociatedTokenAddress(programPDA, CLASH_TOKEN_ACCOUNT);
 
-  let programData = Buffer.alloc(9);
-  programData.writeUInt8(2); // at 0: Instruction type
-  programData.writeBigUInt64LE(BigInt(paymentInfo.CLASHAmount * LAMPORTS_PER_SOL), 1); // at 1: Payed amount
+  let programData = encodeInstruction(2)
+    .u64(BigInt(paymentInfo.CLASHAmount * LAMPORTS_PER_SOL)) // Payed amount
+    .toBuffer();
 
   let clashAuthority = payer;
 
+  let buyerRecordPDA = await getBuyerRecordPDA(exchangerInfo.SOLWallet.publicKey);
+
   const instruction = new TransactionInstruction({
     keys: [
       // User accounts
@@ -281,18 +549,21 @@ export async function confirmCLASHPayment(clashAuthorityInfo: CLASHAuthorityInfo
       {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
 
       // Clash authority accounts
-      {pubkey: clashAuthority.publicKey, isSigner: true, isWritable: false},
+      {pubkey: clashAuthority.publicKey, isSigner: true, isWritable: true},
       {pubkey: clashAuthorityInfo.ATAWallet, isSigner: false, isWritable: true},
 
       // Program account and PDA to sign
       {pubkey: programId, isSigner: false, isWritable: false},
-      {pubkey: programPDA, isSigner: false, isWritable: false},
+      {pubkey: programPDA, isSigner: false, isWritable: true},
 
       // Native system and token programs accounts
       {pubkey: SYSTEM_PROGRAM_ID, isSigner: false, isWritable: false},
       {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
       {pubkey: SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID, isSigner: false, isWritable: false},
       {pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false},
+
+      // Buyer purchases record PDA, created on the first purchase
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true},
     ],
     data: programData,
     programId
@@ -305,22 +576,607 @@ export async function confirmCLASHPayment(clashAuthorityInfo: CLASHAuthorityInfo
   );
 }
 
+/**
+ * Starts instruction data with the instruction type and the sale ID
+ */
+function encodeInstruction(instructionType: number): BorshWriter {
+  return new BorshWriter()
+    .u8(instructionType) // Instruction type
+    .u64(SALE_ID); // Sale ID
+}
+
+/**
+ * Converts an USD value to the integer micro USD representation used on chain
+ */
+function toMicroUSD(usd: number): bigint {
+  return BigInt(Math.round(usd * 1_000_000));
+}
+
+/**
+ * Encodes instruction data for instructions carrying the sale parameters
+ */
+function encodeSaleConfig(instructionType: number, saleConfig: ICOSaleConfig): Buffer {
+  let writer = encodeInstruction(instructionType)
+    .u64(toMicroUSD(saleConfig.CLASHUSD)) // CLASH price in micro USD
+    .u64(toMicroUSD(saleConfig.SOLUSD)); // SOL price in micro USD
+
+  // SOL/USD price source
+  if (saleConfig.pyth) {
+    writer.u8(1)
+      .pubkey(saleConfig.pyth.priceAccount)
+      .u64(BigInt(saleConfig.pyth.maxAgeSlots))
+      .u64(BigInt(saleConfig.pyth.maxAgeSeconds))
+      .u16(saleConfig.pyth.maxConfidenceBps);
+  } else if (saleConfig.priceFeed) {
+    writer.u8(2)
+      .pubkey(saleConfig.priceFeed.publisher)
+      .u64(BigInt(saleConfig.priceFeed.maxAgeSeconds));
+  } else {
+    writer.u8(0);
+  }
+
+  writer
+    .u64(toMicroUSD(saleConfig.minUSD)) // Min micro USD value per exchange
+    .u64(toMicroUSD(saleConfig.maxUSD)) // Max micro USD value per exchange
+    .pubkey(saleConfig.SOLWallet) // Wallet to receive SOL
+    .pubkey(saleConfig.paymentAuthority) // Trusted payment authority
+    .u32(saleConfig.quoteMints.length); // Accepted SPL quote tokens
+
+  for (let quoteMint of saleConfig.quoteMints) {
+    writer.pubkey(quoteMint.mint)
+      .u64(toMicroUSD(quoteMint.USDPrice)); // Quote token price in micro USD
+  }
+
+  writer.u64(toMicroUSD(saleConfig.maxUSDPerWallet)); // Max micro USD value per wallet, 0 for no cap
+
+  // Merkle root of the wallets allowed to buy
+  if (saleConfig.allowlistRoot) {
+    writer.u8(1).bytes(saleConfig.allowlistRoot);
+  } else {
+    writer.u8(0);
+  }
+
+  writer
+    .i64(BigInt(saleConfig.startTimestamp)) // Sale start unix timestamp
+    .i64(BigInt(saleConfig.endTimestamp)) // Sale end unix timestamp
+    .u64(BigInt(saleConfig.extensionWindowSeconds)) // End extension window, 0 to disable
+    .u8(saleConfig.guardian ? 1 : 0)
+    .bytes(saleConfig.guardian ? saleConfig.guardian.toBuffer() : Buffer.alloc(0)) // Key allowed to pause the sale
+    .u64(toMicroUSD(saleConfig.softCapUSD)) // Soft cap in micro USD, 0 for no escrow
+    .u64(toMicroUSD(saleConfig.hardCapUSD)) // Hard cap in micro USD, 0 for no cap
+    .u32(saleConfig.rounds.length); // Price rounds, sold in order
+
+  for (let round of saleConfig.rounds) {
+    writer.u64(toMicroUSD(round.USDPrice)) // CLASH price in micro USD during the round
+      .u64(round.allocation); // CLASH base units sold in the round
+
+    // Optional round window as unix timestamps
+    for (let timestamp of [round.startTimestamp, round.endTimestamp]) {
+      if (timestamp) {
+        writer.u8(1).i64(BigInt(timestamp));
+      } else {
+        writer.u8(0);
+      }
+    }
+  }
+
+  writer.u32(saleConfig.bondingCurve.length); // Bonding curve points, by tokens sold
+
+  for (let point of saleConfig.bondingCurve) {
+    writer.u64(point.CLASHSold) // CLASH base units sold at the point
+      .u64(toMicroUSD(point.USDPrice)); // CLASH price in micro USD at the point
+  }
+
+  // Optional Dutch auction decaying from the sale start
+  if (saleConfig.dutchAuction) {
+    writer.u8(1)
+      .u64(toMicroUSD(saleConfig.dutchAuction.startUSDPrice)) // CLASH price in micro USD at the start
+      .u64(toMicroUSD(saleConfig.dutchAuction.floorUSDPrice)) // CLASH price in micro USD at the end of the decay
+      .u64(BigInt(saleConfig.dutchAuction.durationSeconds)) // Decay duration
+      .u64(BigInt(saleConfig.dutchAuction.stepSeconds)) // Price step duration, 0 for a continuous decay
+      .bool(saleConfig.dutchAuction.rebate); // Escrow SOL and rebate what was paid above the clearing price
+  } else {
+    writer.u8(0);
+  }
+
+  // Optional fair launch selling a fixed allocation pro rata to the SOL committed
+  if (saleConfig.fairLaunch) {
+    writer.u8(1)
+      .u64(saleConfig.fairLaunch.allocation) // CLASH base units sold
+      .u64(BigInt(Math.round(saleConfig.fairLaunch.raiseSOL * LAMPORTS_PER_SOL))) // Lamports raised for the whole allocation
+      .u64(BigInt(Math.round(saleConfig.fairLaunch.maxCommitSOL * LAMPORTS_PER_SOL))); // Max lamports per wallet, 0 for no cap
+  } else {
+    writer.u8(0);
+  }
+
+  // Optional sealed bid batch auction revealed after the sale end
+  if (saleConfig.batchAuction) {
+    writer.u8(1)
+      .u64(saleConfig.batchAuction.supply) // CLASH base units auctioned
+      .u64(BigInt(Math.round(saleConfig.batchAuction.minPriceSOL * LAMPORTS_PER_SOL))) // Min bid in lamports per CLASH
+      .u64(BigInt(Math.round(saleConfig.batchAuction.tickSOL * LAMPORTS_PER_SOL))) // Bid price step in lamports per CLASH
+      .i64(BigInt(saleConfig.batchAuction.revealEndTimestamp)); // Reveal window end unix timestamp
+  } else {
+    writer.u8(0);
+  }
+
+  // Optional vesting of the purchased tokens, released with `claim`
+  if (saleConfig.vesting) {
+    writer.u8(1)
+      .i64(BigInt(saleConfig.vesting.TGETimestamp)) // TGE unix timestamp
+      .u16(Math.round(saleConfig.vesting.TGEUnlockPercent * 100)) // Basis points unlocked at TGE
+      .u64(BigInt(saleConfig.vesting.cliffSeconds)) // Cliff after TGE
+      .u64(BigInt(saleConfig.vesting.durationSeconds)); // Linear vesting duration after the cliff
+  } else {
+    writer.u8(0);
+  }
+
+  // Optional TGE unix timestamp from which held tokens are claimed
+  if (saleConfig.TGETimestamp) {
+    writer.u8(1).i64(BigInt(saleConfig.TGETimestamp));
+  } else {
+    writer.u8(0);
+  }
+
+  return writer.toBuffer();
+}
+
+type PythPriceSource = {
+  priceAccount: PublicKey,
+  maxAgeSlots: number,
+  maxAgeSeconds: number,
+  maxConfidenceBps: number
+};
+
+type PriceFeedSource = {
+  publisher: PublicKey,
+  maxAgeSeconds: number
+};
+
+type PriceRound = {
+  USDPrice: number,
+  allocation: bigint,
+  startTimestamp?: number,
+  endTimestamp?: number
+};
+
+type CurvePoint = {
+  CLASHSold: bigint,
+  USDPrice: number
+};
+
+type DutchAuction = {
+  startUSDPrice: number,
+  floorUSDPrice: number,
+  durationSeconds: number,
+  stepSeconds: number,
+  rebate: boolean
+};
+
+type FairLaunch = {
+  allocation: bigint,
+  raiseSOL: number,
+  maxCommitSOL: number
+};
+
+type BatchAuction = {
+  supply: bigint,
+  minPriceSOL: number,
+  tickSOL: number,
+  revealEndTimestamp: number
+};
+
+type VestingSchedule = {
+  TGETimestamp: number,
+  TGEUnlockPercent: number,
+  cliffSeconds: number,
+  durationSeconds: number
+};
+
+type SealedBid = {
+  priceLamports: bigint,
+  CLASHAmount: bigint,
+  salt: Buffer
+};
+
+type QuoteMintConfig = {
+  mint: PublicKey,
+  USDPrice: number
+};
+
+type ICOSaleConfig = {
+  CLASHUSD: number,
+  SOLUSD: number,
+  pyth?: PythPriceSource,
+  priceFeed?: PriceFeedSource,
+  minUSD: number,
+  maxUSD: number,
+  SOLWallet: PublicKey,
+  paymentAuthority: PublicKey,
+  quoteMints: QuoteMintConfig[],
+  maxUSDPerWallet: number,
+  allowlistRoot?: Buffer,
+  startTimestamp: number,
+  endTimestamp: number,
+  extensionWindowSeconds: number,
+  guardian?: PublicKey,
+  softCapUSD: number,
+  hardCapUSD: number,
+  rounds: PriceRound[],
+  bondingCurve: CurvePoint[],
+  dutchAuction?: DutchAuction,
+  fairLaunch?: FairLaunch,
+  batchAuction?: BatchAuction,
+  vesting?: VestingSchedule,
+  TGETimestamp?: number
+};
+
+type AllowlistProof = {
+  allocationUSD?: number,
+  proof: Buffer[]
+};
+
 type CLASHAuthorityInfo = {
   SOLWallet: PublicKey,
   ATAWallet: PublicKey
 };
 
+/**
+ * Settles an ended soft cap sale, callable by anyone: releases the escrowed SOL to the
+ * CLASH wallet if the soft cap was reached, otherwise opens refunds
+ */
+export async function finalizeSale(clashAuthorityInfo: CLASHAuthorityInfo, caller: Keypair) : Promise<void> {
+  console.log(`Finalizing ICO sale.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+  let vaultPDA = await getVaultPDA();
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      {pubkey: caller.publicKey, isSigner: true, isWritable: false},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA and its escrow vault
+      {pubkey: programPDA, isSigner: false, isWritable: true},
+      {pubkey: vaultPDA, isSigner: false, isWritable: true},
+
+      // CLASH wallet receiving the escrowed SOL
+      {pubkey: clashAuthorityInfo.SOLWallet, isSigner: false, isWritable: true}
+    ],
+    programId,
+    data: encodeInstruction(10).toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [caller]
+  );
+}
+
+/**
+ * Returns the escrowed SOL of a buyer when the sale missed its soft cap, was cancelled or
+ * oversubscribed its fair launch. Anyone can send the refund on behalf of the buyer
+ */
+export async function refundPurchase(exchangerInfo: ExchangerInfo, buyer?: PublicKey) : Promise<void> {
+  let buyerWallet = buyer ?? exchangerInfo.SOLWallet.publicKey;
+
+  console.log(`Refunding escrowed SOL to ${buyerWallet.toBase58()}.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+  let vaultPDA = await getVaultPDA();
+  let buyerRecordPDA = await getBuyerRecordPDA(buyerWallet);
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      {pubkey: buyerWallet, isSigner: false, isWritable: true},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA and its escrow vault
+      {pubkey: programPDA, isSigner: false, isWritable: true},
+      {pubkey: vaultPDA, isSigner: false, isWritable: true},
+
+      // Buyer purchases record PDA
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true}
+    ],
+    programId,
+    data: encodeInstruction(11).toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [exchangerInfo.SOLWallet]
+  );
+}
+
+/**
+ * Transfers the CLASH tokens bought by a buyer once the sale is finalized, vested so far or
+ * distributed at the TGE. Claims can be sent on behalf of another buyer, the exchanger paying
+ * for its token account
+ */
+export async function claimTokens(exchangerInfo: ExchangerInfo, buyer?: PublicKey) : Promise<void> {
+  let buyerWallet = buyer ?? exchangerInfo.SOLWallet.publicKey;
+  let buyerATA = buyer ? await findAssociatedTokenAddress(buyer, CLASH_TOKEN_ACCOUNT) : exchangerInfo.ATAWallet;
+
+  console.log(`Claiming CLASH tokens for ${buyerWallet.toBase58()}.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+  let programATA = await findAssociatedTokenAddress(programPDA, CLASH_TOKEN_ACCOUNT);
+  let buyerRecordPDA = await getBuyerRecordPDA(buyerWallet);
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      // User accounts
+      {pubkey: buyerWallet, isSigner: false, isWritable: false},
+      {pubkey: buyerATA, isSigner: false, isWritable: true},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA and its associated token account
+      {pubkey: programPDA, isSigner: false, isWritable: true},
+      {pubkey: programATA, isSigner: false, isWritable: true},
+
+      // Buyer purchases record PDA
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true},
+
+      // Native system and token programs accounts
+      {pubkey: SYSTEM_PROGRAM_ID, isSigner: false, isWritable: false},
+      {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+      {pubkey: SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID, isSigner: false, isWritable: false},
+      {pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false},
+
+      // Account paying for the buyer token account
+      {pubkey: exchangerInfo.SOLWallet.publicKey, isSigner: true, isWritable: true}
+    ],
+    programId,
+    data: encodeInstruction(12).toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [exchangerInfo.SOLWallet]
+  );
+}
+
+/**
+ * Commits SOL to a fair launch, settled pro rata with `claim` and `refund` once the sale is finalized
+ */
+export async function commitFairLaunch(exchangerInfo: ExchangerInfo, SOLAmount: number) : Promise<void> {
+  console.log(`Committing ${SOLAmount} SOL to the fair launch.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+  let programATA = await findAssociatedTokenAddress(programPDA, CLASH_TOKEN_ACCOUNT);
+  let vaultPDA = await getVaultPDA();
+  let buyerRecordPDA = await getBuyerRecordPDA(exchangerInfo.SOLWallet.publicKey);
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      {pubkey: exchangerInfo.SOLWallet.publicKey, isSigner: true, isWritable: true},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA, its token account holding the allocation and its escrow vault
+      {pubkey: programPDA, isSigner: false, isWritable: true},
+      {pubkey: programATA, isSigner: false, isWritable: false},
+      {pubkey: vaultPDA, isSigner: false, isWritable: true},
+
+      // Buyer purchases record PDA
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true},
+
+      // Native system program account
+      {pubkey: SYSTEM_PROGRAM_ID, isSigner: false, isWritable: false}
+    ],
+    programId,
+    data: encodeInstruction(14)
+      .u64(BigInt(Math.round(SOLAmount * LAMPORTS_PER_SOL))) // Lamports to commit
+      .toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [exchangerInfo.SOLWallet]
+  );
+}
+
+/**
+ * Places a sealed bid in a batch auction, only its commitment is sent along with the escrowed SOL
+ */
+export async function placeBid(exchangerInfo: ExchangerInfo, bid: SealedBid, escrowSOL: number) : Promise<void> {
+  console.log(`Placing a sealed bid escrowing ${escrowSOL} SOL.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+  let programATA = await findAssociatedTokenAddress(programPDA, CLASH_TOKEN_ACCOUNT);
+  let vaultPDA = await getVaultPDA();
+  let buyerRecordPDA = await getBuyerRecordPDA(exchangerInfo.SOLWallet.publicKey);
+
+  let commitment = Buffer.from(keccak_256.arrayBuffer(Buffer.concat([
+    exchangerInfo.SOLWallet.publicKey.toBuffer(),
+    new BorshWriter().u64(bid.priceLamports).u64(bid.CLASHAmount).toBuffer(),
+    bid.salt
+  ])));
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      {pubkey: exchangerInfo.SOLWallet.publicKey, isSigner: true, isWritable: true},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA, its token account holding the supply and its escrow vault
+      {pubkey: programPDA, isSigner: false, isWritable: true},
+      {pubkey: programATA, isSigner: false, isWritable: false},
+      {pubkey: vaultPDA, isSigner: false, isWritable: true},
+
+      // Buyer purchases record PDA
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true},
+
+      // Native system program account
+      {pubkey: SYSTEM_PROGRAM_ID, isSigner: false, isWritable: false}
+    ],
+    programId,
+    data: encodeInstruction(15)
+      .bytes(commitment) // keccak256(bidder || price || quantity || salt)
+      .u64(BigInt(Math.round(escrowSOL * LAMPORTS_PER_SOL))) // Lamports to escrow
+      .toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [exchangerInfo.SOLWallet]
+  );
+}
+
+/**
+ * Reveals a sealed bid once the batch auction sale has ended
+ */
+export async function revealBid(exchangerInfo: ExchangerInfo, bid: SealedBid) : Promise<void> {
+  console.log(`Revealing a bid of ${bid.CLASHAmount} CLASH base units at ${bid.priceLamports} lamports per CLASH.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+  let buyerRecordPDA = await getBuyerRecordPDA(exchangerInfo.SOLWallet.publicKey);
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      {pubkey: exchangerInfo.SOLWallet.publicKey, isSigner: true, isWritable: false},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA
+      {pubkey: programPDA, isSigner: false, isWritable: true},
+
+      // Buyer purchases record PDA
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true}
+    ],
+    programId,
+    data: encodeInstruction(16)
+      .u64(bid.priceLamports) // Lamports per CLASH
+      .u64(bid.CLASHAmount) // CLASH base units
+      .bytes(bid.salt)
+      .toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [exchangerInfo.SOLWallet]
+  );
+}
+
+/**
+ * Transfers the CLASH tokens won by a bid at the clearing price and refunds the rest of its escrow.
+ * Bids can be settled on behalf of another bidder, the exchanger paying for its token account
+ */
+export async function settleBid(clashAuthorityInfo: CLASHAuthorityInfo, exchangerInfo: ExchangerInfo, bidder?: PublicKey) : Promise<void> {
+  let bidderWallet = bidder ?? exchangerInfo.SOLWallet.publicKey;
+  let bidderATA = bidder ? await findAssociatedTokenAddress(bidder, CLASH_TOKEN_ACCOUNT) : exchangerInfo.ATAWallet;
+
+  console.log(`Settling batch auction bid of ${bidderWallet.toBase58()}.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+  let programATA = await findAssociatedTokenAddress(programPDA, CLASH_TOKEN_ACCOUNT);
+  let vaultPDA = await getVaultPDA();
+  let buyerRecordPDA = await getBuyerRecordPDA(bidderWallet);
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      // User accounts
+      {pubkey: bidderWallet, isSigner: false, isWritable: true},
+      {pubkey: bidderATA, isSigner: false, isWritable: true},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA, its associated token account and escrow vault
+      {pubkey: programPDA, isSigner: false, isWritable: true},
+      {pubkey: programATA, isSigner: false, isWritable: true},
+      {pubkey: vaultPDA, isSigner: false, isWritable: true},
+
+      // CLASH wallet receiving the SOL paid at the clearing price
+      {pubkey: clashAuthorityInfo.SOLWallet, isSigner: false, isWritable: true},
+
+      // Buyer purchases record PDA
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true},
+
+      // Native system and token programs accounts
+      {pubkey: SYSTEM_PROGRAM_ID, isSigner: false, isWritable: false},
+      {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+      {pubkey: SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID, isSigner: false, isWritable: false},
+      {pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false},
+
+      // Account paying for the bidder token account
+      {pubkey: exchangerInfo.SOLWallet.publicKey, isSigner: true, isWritable: true}
+    ],
+    programId,
+    data: encodeInstruction(17).toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [exchangerInfo.SOLWallet]
+  );
+}
+
+/**
+ * Returns the SOL a buyer paid above the Dutch auction clearing price once the sale is finalized,
+ * anyone can claim it on behalf of the buyer
+ */
+export async function claimRebate(exchangerInfo: ExchangerInfo, buyer?: PublicKey) : Promise<void> {
+  let buyerWallet = buyer ?? exchangerInfo.SOLWallet.publicKey;
+
+  console.log(`Claiming Dutch auction rebate for ${buyerWallet.toBase58()}.`)
+
+  let [programPDA, seed] = await getProgramPDA();
+  let vaultPDA = await getVaultPDA();
+  let buyerRecordPDA = await getBuyerRecordPDA(buyerWallet);
+
+  const instruction = new TransactionInstruction({
+    keys: [
+      {pubkey: buyerWallet, isSigner: false, isWritable: true},
+
+      // Token account
+      {pubkey: CLASH_TOKEN_ACCOUNT, isSigner: false, isWritable: false},
+
+      // Program PDA and its escrow vault
+      {pubkey: programPDA, isSigner: false, isWritable: true},
+      {pubkey: vaultPDA, isSigner: false, isWritable: true},
+
+      // Buyer purchases record PDA
+      {pubkey: buyerRecordPDA, isSigner: false, isWritable: true}
+    ],
+    programId,
+    data: encodeInstruction(13).toBuffer()
+  });
+
+  await sendAndConfirmTransaction(
+    connection,
+    new Transaction().add(instruction),
+    [exchangerInfo.SOLWallet]
+  );
+}
+
 export async function terminateICO(clashAuthorityInfo: CLASHAuthorityInfo, initializer:Keypair) : Promise<void> {
   console.log(`Terminating the ICO program.`)
 
   let [programPDA, seed] = await getProgramPDA();
+  let vaultPDA = await getVaultPDA();
 
   clashAuthorityInfo.ATAWallet = await findAssociatedTokenAddress(programPDA, CLASH_TOKEN_ACCOUNT);
 
   let initializerATA = await findAssociatedTokenAddress(initializer.publicKey, CLASH_TOKEN_ACCOUNT);
 
-  let programData = Buffer.alloc(1);
-  programData.writeUInt8(3); // at 0: Instruction type
+  let programData = encodeInstruction(3).toBuffer();
 
   const instruction = new TransactionInstruction({
     keys: [
@@ -338,7 +1194,11 @@ export async function terminateICO(clashAuthorityInfo: CLASHAuthorityInfo, initi
       {pubkey: clashAuthorityInfo.ATAWallet, isSigner: false, isWritable: true},
 
       // Native system and token programs accounts
-      {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false}
+      {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+
+      // Escrow vault PDA, emptied into the CLASH wallet
+      {pubkey: vaultPDA, isSigner: false, isWritable: true},
+      {pubkey: clashAuthorityInfo.SOLWallet, isSigner: false, isWritable: true}
     ],
     programId,
     data: programData
@@ -379,6 +1239,27 @@ export async function loadExchangerInfoFromFile(walletFilePath: string) : Promis
 
 type ExchangeSOLByCLASHInfo = {
   SOLAmount: number,
+  minCLASHOut?: bigint,
+  expirySlots?: number,
+  priceAccount?: PublicKey,
+  allowlistProof?: AllowlistProof,
+  allowPartialFill?: boolean,
+};
+
+type BuyExactCLASHInfo = {
+  CLASHAmount: bigint,
+  maxSOLAmount: number,
+  priceAccount?: PublicKey,
+  expirySlots?: number,
+  allowlistProof?: AllowlistProof,
+};
+
+type ExchangeSPLByCLASHInfo = {
+  quoteMint: PublicKey,
+  quoteAmount: bigint,
+  minCLASHOut?: bigint,
+  expirySlots?: number,
+  allowlistProof?: AllowlistProof,
 };
 
 type CLASHPaymentInfo = {
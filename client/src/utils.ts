@@ -88,4 +88,70 @@ export async function findAssociatedTokenAddress(
       ],
       SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID
   ))[0];
-}
\ No newline at end of file
+}
+/**
+ * Minimal Borsh encoder for the program instruction data
+ */
+export class BorshWriter {
+  private chunks: Buffer[] = [];
+
+  u8(value: number): BorshWriter {
+    let buffer = Buffer.alloc(1);
+    buffer.writeUInt8(value);
+    this.chunks.push(buffer);
+    return this;
+  }
+
+  u16(value: number): BorshWriter {
+    let buffer = Buffer.alloc(2);
+    buffer.writeUInt16LE(value);
+    this.chunks.push(buffer);
+    return this;
+  }
+
+  u32(value: number): BorshWriter {
+    let buffer = Buffer.alloc(4);
+    buffer.writeUInt32LE(value);
+    this.chunks.push(buffer);
+    return this;
+  }
+
+  i32(value: number): BorshWriter {
+    let buffer = Buffer.alloc(4);
+    buffer.writeInt32LE(value);
+    this.chunks.push(buffer);
+    return this;
+  }
+
+  u64(value: bigint): BorshWriter {
+    let buffer = Buffer.alloc(8);
+    buffer.writeBigUInt64LE(value);
+    this.chunks.push(buffer);
+    return this;
+  }
+
+  i64(value: bigint): BorshWriter {
+    let buffer = Buffer.alloc(8);
+    buffer.writeBigInt64LE(value);
+    this.chunks.push(buffer);
+    return this;
+  }
+
+  bool(value: boolean): BorshWriter {
+    return this.u8(value ? 1 : 0);
+  }
+
+  pubkey(value: PublicKey): BorshWriter {
+    this.chunks.push(value.toBuffer());
+    return this;
+  }
+
+  bytes(value: Buffer): BorshWriter {
+    this.chunks.push(value);
+    return this;
+  }
+
+  toBuffer(): Buffer {
+    return Buffer.concat(this.chunks);
+  }
+}
@@ -0,0 +1,12 @@
+// Seeds used to generate the PDA to sign transactions
+pub const PROGRAM_PDA_SEED1: &[u8] = b"genezys-fin";
+pub const PROGRAM_PDA_SEED2: &[u8] = b"clash-ico";
+
+// Seed used along with the publisher key to generate the price feed PDA
+pub const PRICE_FEED_SEED: &[u8] = b"price-feed";
+
+// Seed used along with the sale PDA and the buyer key to generate the buyer record PDA
+pub const BUYER_RECORD_SEED: &[u8] = b"buyer-record";
+
+// Seed used along with the sale PDA to generate the vault PDA escrowing SOL of soft cap sales
+pub const VAULT_SEED: &[u8] = b"vault";
@@ -13,7 +13,7 @@ pub enum ICOError {
     InvalidProgramInstruction,
 
     // General errors
-    #[error("Invalid Clash token ID")]
+    #[error("Invalid Clash token ID: mint does not match the sale token")]
     InvalidClashTokenId,
 
     #[error("Program account PDA does not match the expected PDA")]
@@ -70,6 +70,253 @@ pub enum ICOError {
 
     #[error("Incorrect initializer associated token account")]
     InitializerAssociatedAccountMismatch,
+
+    // Error codes are part of the program interface: new variants are only appended below
+
+    // On chain sale data
+    #[error("There is not an initialized ICO for the program PDA")]
+    UninitializedICO,
+
+    // Sale config validation
+    #[error("Invalid sale config: CLASH price in USD must be greater than zero")]
+    InvalidConfigClashPrice,
+
+    #[error("Invalid sale config: SOL price in USD must be greater than zero")]
+    InvalidConfigSolPrice,
+
+    #[error("Invalid sale config: min USD value must be lower than max USD value")]
+    InvalidConfigUsdLimits,
+
+    #[error("Invalid sale config: SOL destination wallet must be a valid address")]
+    InvalidConfigSolWallet,
+
+    #[error("Invalid sale config: payment authority must be a valid address")]
+    InvalidConfigPaymentAuthority,
+
+    // Pricing math
+    #[error("Arithmetic overflow while computing prices")]
+    PricingOverflow,
+
+    #[error("Division by zero while computing prices")]
+    PricingDivisionByZero,
+
+    // SOL/USD oracle
+    #[error("Invalid sale config: oracle price account and limits must be set")]
+    InvalidConfigPriceSource,
+
+    #[error("Price account does not match the sale price source")]
+    OraclePriceAccountMismatch,
+
+    #[error("Invalid oracle price account data")]
+    InvalidOraclePriceAccount,
+
+    #[error("Oracle price is not currently trading")]
+    OraclePriceNotTrading,
+
+    #[error("Oracle price must be greater than zero")]
+    InvalidOraclePrice,
+
+    #[error("Oracle price is older than the allowed max age")]
+    OraclePriceStale,
+
+    #[error("Oracle price confidence interval is wider than allowed")]
+    OraclePriceUncertain,
+
+    // SOL/USD price feed
+    #[error("Price feed PDA does not match the expected PDA for the publisher")]
+    InvalidAddressPriceFeedPDA,
+
+    #[error("Price feed is not owned by the program")]
+    InvalidPriceFeedOwner,
+
+    #[error("Price feed exponent is out of the supported range")]
+    InvalidPriceFeedExponent,
+
+    // Versioned exchange data
+    #[error("Unsupported exchange instruction data version")]
+    InvalidExchangeDataVersion,
+
+    #[error("Exchange would return fewer Clash tokens than the requested minimum")]
+    ExchangeSlippageExceeded,
+
+    #[error("Exchange deadline slot has passed")]
+    ExchangeDeadlineExpired,
+
+    // Exact output exchange
+    #[error("Exchange would cost more than the requested maximum")]
+    ExchangeMaxInputExceeded,
+
+    // SPL payments
+    #[error("Invalid sale config: accepted quote mints must be unique, valid and priced")]
+    InvalidConfigQuoteMints,
+
+    #[error("Quote token mint is not accepted as payment by this sale")]
+    QuoteMintNotAccepted,
+
+    // Buyer records
+    #[error("Invalid sale config: max USD value per wallet must not be lower than min USD value")]
+    InvalidConfigWalletCap,
+
+    #[error("Unexpected address for the buyer record(PDA)")]
+    InvalidAddressBuyerRecordPDA,
+
+    #[error("Buyer record account is not owned by the program")]
+    InvalidBuyerRecordOwner,
+
+    #[error("Purchase would exceed the max USD value allowed per wallet")]
+    WalletCapExceeded,
+
+    // Allowlist
+    #[error("Buyer is not in the sale allowlist or its Merkle proof is invalid")]
+    InvalidAllowlistProof,
+
+    // Sale time window
+    #[error("Invalid sale config: sale start time must be before its end time")]
+    InvalidConfigSaleWindow,
+
+    #[error("Sale has not started yet")]
+    SaleNotStarted,
+
+    #[error("Sale has already ended")]
+    SaleEnded,
+
+    // Sale status
+    #[error("Instruction is not allowed in the current sale status")]
+    InvalidSaleStatus,
+
+    #[error("Sale cannot move from its current status to the requested one")]
+    InvalidSaleStatusTransition,
+
+    // Pause
+    #[error("Sale is paused")]
+    SalePaused,
+
+    #[error("Only the initializer or the sale guardian can pause the sale")]
+    InvalidPauseAuthority,
+
+    // Soft cap escrow
+    #[error("Unexpected address for the sale escrow vault(PDA)")]
+    InvalidAddressVaultPDA,
+
+    #[error("Sales escrowing payments only accept SOL")]
+    EscrowRequiresSOL,
+
+    #[error("There is no buyer record for the buyer in this sale")]
+    UninitializedBuyerRecord,
+
+    #[error("Buyer has no escrowed lamports to refund")]
+    NothingToRefund,
+
+    #[error("Buyer has no Clash tokens to claim")]
+    NothingToClaim,
+
+    #[error("Sale still holds escrowed lamports to release or refund")]
+    SaleHasEscrowedFunds,
+
+    #[error("Sale still holds Clash tokens claimable by buyers")]
+    SaleHasOutstandingClaims,
+
+    // Update sale config
+    #[error("Sale config field cannot change once the sale has buyers")]
+    LockedConfigChange,
+
+    // Hard cap
+    #[error(
+        "Invalid sale config: hard cap must not be lower than the soft cap nor the min USD value"
+    )]
+    InvalidConfigHardCap,
+
+    #[error("Sale hard cap has been reached")]
+    HardCapReached,
+
+    #[error("Purchase would exceed the sale hard cap")]
+    HardCapExceeded,
+
+    // Price rounds
+    #[error("Invalid sale config: price rounds must be priced, allocated and have a valid window")]
+    InvalidConfigRounds,
+
+    #[error("No price round is currently open")]
+    NoOpenPriceRound,
+
+    // Bonding curve
+    #[error("Invalid sale config: bonding curve must start at zero tokens sold with rising points and prices, and cannot be combined with price rounds")]
+    InvalidConfigBondingCurve,
+
+    // Dutch auction
+    #[error("Invalid sale config: Dutch auction must decay from its start price down to a positive floor over a positive duration, and cannot be combined with other pricing modes")]
+    InvalidConfigDutchAuction,
+
+    #[error("Sale is not a Dutch auction with rebates")]
+    NoAuctionRebate,
+
+    #[error("Dutch auction with rebates cannot be cancelled once buyers escrowed lamports")]
+    RebateAuctionNotCancellable,
+
+    // Fair launch
+    #[error("Invalid sale config: fair launch must have an allocation and a SOL target, and cannot be combined with caps, an allowlist or other pricing modes")]
+    InvalidConfigFairLaunch,
+
+    #[error("Sale is not a fair launch")]
+    NotFairLaunch,
+
+    #[error("Fair launch sales only accept SOL commitments")]
+    FairLaunchCommitOnly,
+
+    #[error("Commitment must be greater than zero")]
+    InvalidCommitmentAmount,
+
+    #[error("Commitment would exceed the max lamports allowed per wallet")]
+    CommitmentCapExceeded,
+
+    // Batch auction
+    #[error("Invalid sale config: batch auction must have a supply, positive price ticks and a reveal window after the sale end without extension, and cannot be combined with caps, an allowlist or other pricing modes")]
+    InvalidConfigBatchAuction,
+
+    #[error("Sale is not a batch auction")]
+    NotBatchAuction,
+
+    #[error("Batch auction sales only accept sealed bids")]
+    BatchAuctionBidOnly,
+
+    #[error("Buyer already placed a bid in this auction")]
+    BidAlreadyPlaced,
+
+    #[error("Buyer has no bid in this auction")]
+    NoBid,
+
+    #[error("Bid was already revealed")]
+    BidAlreadyRevealed,
+
+    #[error("Revealed bid does not match its commitment")]
+    BidRevealMismatch,
+
+    #[error("Bid price is not one of the auction price ticks")]
+    InvalidBidPrice,
+
+    #[error("Escrowed lamports do not cover the revealed bid")]
+    InsufficientBidEscrow,
+
+    #[error("Bid reveal window is closed")]
+    BidRevealClosed,
+
+    #[error("Bid reveal window is still open")]
+    BidRevealOpen,
+
+    // Vesting
+    #[error("Invalid sale config: vesting TGE unlock must not exceed 100%, and vesting cannot be combined with a soft cap, a fair launch or a batch auction")]
+    InvalidConfigVesting,
+
+    // Deferred claims
+    #[error("Invalid sale config: TGE claims cannot be combined with vesting or a batch auction")]
+    InvalidConfigTGE,
+
+    #[error("Clash tokens cannot be claimed before the TGE")]
+    ClaimBeforeTGE,
+
+    // Pause guardian
+    #[error("Invalid sale config: guardian must be a valid address")]
+    InvalidConfigGuardian,
 }
 
 impl From<ICOError> for ProgramError {
@@ -85,7 +332,12 @@ impl<E> DecodeError<E> for ICOError {
 }
 
 pub fn ico_err(err: ICOError) -> ProgramResult {
+    Err(ico_error(err))
+}
+
+// Logs and converts an ICOError, meant for `ok_or_else`/`map_err` in helpers returning values
+pub fn ico_error(err: ICOError) -> ProgramError {
     let err_code: u32 = err as u32;
     msg!("[ICOError #{}] Reason: '{}'", err_code, err);
-    Err(err.into())
+    err.into()
 }
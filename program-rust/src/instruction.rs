@@ -2,16 +2,81 @@ use solana_program::program_error::ProgramError;
 
 use crate::error::{ico_err, ICOError};
 
-use crate::state::{ClashTokenExchangeData, ClashTokenPaymentData};
+use crate::state::{
+    BidCommitData, BidRevealData, ClashTokenExactOutData, ClashTokenExchangeData,
+    ClashTokenPaymentData, ClashTokenSPLExchangeData, FairLaunchCommitData, ICOSaleConfig,
+    PriceFeedUpdateData,
+};
 
 use borsh::BorshDeserialize;
 
+// Every instruction data starts with the instruction type, sale instructions are
+// followed by the ID of the sale they operate on
 #[derive(PartialEq)]
 pub enum ProgramInstruction {
-    InitializeICO,
-    ExchangeClashToken { data: ClashTokenExchangeData },
-    ExecuteClashPayment { data: ClashTokenPaymentData },
-    TerminateICO,
+    InitializeICO {
+        sale_id: u64,
+        data: ICOSaleConfig,
+    },
+    ExchangeClashToken {
+        sale_id: u64,
+        data: ClashTokenExchangeData,
+    },
+    ExecuteClashPayment {
+        sale_id: u64,
+        data: ClashTokenPaymentData,
+    },
+    TerminateICO {
+        sale_id: u64,
+    },
+    UpdateSaleConfig {
+        sale_id: u64,
+        data: ICOSaleConfig,
+    },
+    UpdatePrice {
+        data: PriceFeedUpdateData,
+    },
+    ExchangeClashTokenExactOut {
+        sale_id: u64,
+        data: ClashTokenExactOutData,
+    },
+    ExchangeClashTokenWithSPL {
+        sale_id: u64,
+        data: ClashTokenSPLExchangeData,
+    },
+    PauseSale {
+        sale_id: u64,
+    },
+    ResumeSale {
+        sale_id: u64,
+    },
+    FinalizeSale {
+        sale_id: u64,
+    },
+    RefundPurchase {
+        sale_id: u64,
+    },
+    ClaimTokens {
+        sale_id: u64,
+    },
+    ClaimRebate {
+        sale_id: u64,
+    },
+    CommitFairLaunch {
+        sale_id: u64,
+        data: FairLaunchCommitData,
+    },
+    PlaceBid {
+        sale_id: u64,
+        data: BidCommitData,
+    },
+    RevealBid {
+        sale_id: u64,
+        data: BidRevealData,
+    },
+    SettleBid {
+        sale_id: u64,
+    },
 
     // Internal usage only
     InvalidInstruction,
@@ -27,14 +92,72 @@ impl ProgramInstruction {
         let instruction_data: &[u8] = &input_data[1..];
 
         let instruction: ProgramInstruction = match instruction_type {
-            0 => ProgramInstruction::InitializeICO,
-            1 => ProgramInstruction::ExchangeClashToken {
-                data: ClashTokenExchangeData::try_from_slice(instruction_data)?,
+            0 => {
+                let (sale_id, data) = Self::unpack_sale_data(instruction_data)?;
+                ProgramInstruction::InitializeICO { sale_id, data }
+            }
+            1 => {
+                let (sale_id, data) = Self::unpack_sale_id(instruction_data)?;
+                ProgramInstruction::ExchangeClashToken {
+                    sale_id,
+                    data: ClashTokenExchangeData::unpack(data)?,
+                }
+            }
+            2 => {
+                let (sale_id, data) = Self::unpack_sale_data(instruction_data)?;
+                ProgramInstruction::ExecuteClashPayment { sale_id, data }
+            }
+            3 => ProgramInstruction::TerminateICO {
+                sale_id: u64::try_from_slice(instruction_data)?,
             },
-            2 => ProgramInstruction::ExecuteClashPayment {
-                data: ClashTokenPaymentData::try_from_slice(instruction_data)?,
+            4 => {
+                let (sale_id, data) = Self::unpack_sale_data(instruction_data)?;
+                ProgramInstruction::UpdateSaleConfig { sale_id, data }
+            }
+            5 => ProgramInstruction::UpdatePrice {
+                data: PriceFeedUpdateData::try_from_slice(instruction_data)?,
+            },
+            6 => {
+                let (sale_id, data) = Self::unpack_sale_data(instruction_data)?;
+                ProgramInstruction::ExchangeClashTokenExactOut { sale_id, data }
+            }
+            7 => {
+                let (sale_id, data) = Self::unpack_sale_data(instruction_data)?;
+                ProgramInstruction::ExchangeClashTokenWithSPL { sale_id, data }
+            }
+            8 => ProgramInstruction::PauseSale {
+                sale_id: u64::try_from_slice(instruction_data)?,
+            },
+            9 => ProgramInstruction::ResumeSale {
+                sale_id: u64::try_from_slice(instruction_data)?,
+            },
+            10 => ProgramInstruction::FinalizeSale {
+                sale_id: u64::try_from_slice(instruction_data)?,
+            },
+            11 => ProgramInstruction::RefundPurchase {
+                sale_id: u64::try_from_slice(instruction_data)?,
+            },
+            12 => ProgramInstruction::ClaimTokens {
+                sale_id: u64::try_from_slice(instruction_data)?,
+            },
+            13 => ProgramInstruction::ClaimRebate {
+                sale_id: u64::try_from_slice(instruction_data)?,
+            },
+            14 => {
+                let (sale_id, data) = Self::unpack_sale_data(instruction_data)?;
+                ProgramInstruction::CommitFairLaunch { sale_id, data }
+            }
+            15 => {
+                let (sale_id, data) = Self::unpack_sale_data(instruction_data)?;
+                ProgramInstruction::PlaceBid { sale_id, data }
+            }
+            16 => {
+                let (sale_id, data) = Self::unpack_sale_data(instruction_data)?;
+                ProgramInstruction::RevealBid { sale_id, data }
+            }
+            17 => ProgramInstruction::SettleBid {
+                sale_id: u64::try_from_slice(instruction_data)?,
             },
-            3 => ProgramInstruction::TerminateICO,
             _ => ProgramInstruction::InvalidInstruction,
         };
 
@@ -44,4 +167,15 @@ impl ProgramInstruction {
 
         Ok(instruction)
     }
+
+    // Sale instructions data starts with the sale ID followed by the instruction payload
+    fn unpack_sale_id(mut data: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        let sale_id = u64::deserialize(&mut data)?;
+        Ok((sale_id, data))
+    }
+
+    fn unpack_sale_data<T: BorshDeserialize>(data: &[u8]) -> Result<(u64, T), ProgramError> {
+        let (sale_id, data) = Self::unpack_sale_id(data)?;
+        Ok((sale_id, T::try_from_slice(data)?))
+    }
 }
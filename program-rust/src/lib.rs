@@ -1,7 +1,10 @@
 pub mod config;
 pub mod error;
 pub mod instruction;
+pub mod oracle;
+pub mod pricing;
 pub mod processor;
+pub mod schedule;
 pub mod state;
 pub mod util;
 
@@ -0,0 +1,454 @@
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+
+use borsh::BorshDeserialize;
+
+use crate::error::{ico_error, ICOError};
+
+use crate::pricing::{mul_div, to_u64, token_unit, Rounding, USD_DECIMALS};
+
+use crate::state::{ICOSaleConfig, PriceFeed, SolPriceSource};
+
+use crate::util::find_price_feed_pda;
+
+// Pyth v2 price account header values
+pub const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+pub const PYTH_VERSION_2: u32 = 2;
+pub const PYTH_ACCOUNT_TYPE_PRICE: u32 = 3;
+pub const PYTH_STATUS_TRADING: u32 = 1;
+
+// Byte offsets of the fields read from a Pyth v2 price account
+const PYTH_MAGIC_OFFSET: usize = 0;
+const PYTH_VERSION_OFFSET: usize = 4;
+const PYTH_ACCOUNT_TYPE_OFFSET: usize = 8;
+const PYTH_EXPO_OFFSET: usize = 20;
+const PYTH_TIMESTAMP_OFFSET: usize = 96;
+const PYTH_AGG_PRICE_OFFSET: usize = 208;
+const PYTH_AGG_CONF_OFFSET: usize = 216;
+const PYTH_AGG_STATUS_OFFSET: usize = 224;
+const PYTH_AGG_PUB_SLOT_OFFSET: usize = 232;
+
+// Minimum account size holding every field above
+pub const PYTH_PRICE_ACCOUNT_MIN_LEN: usize = 240;
+
+// Aggregate price published in a Pyth price account, value is `price * 10^expo`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PythPrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub status: u32,
+    pub publish_slot: u64,
+    pub timestamp: i64,
+}
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    bytes.copy_from_slice(&data[offset..offset + N]);
+    bytes
+}
+
+// Parses the aggregate price of a Pyth v2 price account
+pub fn parse_pyth_price(data: &[u8]) -> Result<PythPrice, ProgramError> {
+    if data.len() < PYTH_PRICE_ACCOUNT_MIN_LEN
+        || u32::from_le_bytes(read_bytes(data, PYTH_MAGIC_OFFSET)) != PYTH_MAGIC
+        || u32::from_le_bytes(read_bytes(data, PYTH_VERSION_OFFSET)) != PYTH_VERSION_2
+        || u32::from_le_bytes(read_bytes(data, PYTH_ACCOUNT_TYPE_OFFSET)) != PYTH_ACCOUNT_TYPE_PRICE
+    {
+        return Err(ico_error(ICOError::InvalidOraclePriceAccount));
+    }
+
+    Ok(PythPrice {
+        price: i64::from_le_bytes(read_bytes(data, PYTH_AGG_PRICE_OFFSET)),
+        conf: u64::from_le_bytes(read_bytes(data, PYTH_AGG_CONF_OFFSET)),
+        expo: i32::from_le_bytes(read_bytes(data, PYTH_EXPO_OFFSET)),
+        status: u32::from_le_bytes(read_bytes(data, PYTH_AGG_STATUS_OFFSET)),
+        publish_slot: u64::from_le_bytes(read_bytes(data, PYTH_AGG_PUB_SLOT_OFFSET)),
+        timestamp: i64::from_le_bytes(read_bytes(data, PYTH_TIMESTAMP_OFFSET)),
+    })
+}
+
+// Converts a price of `price * 10^expo` USD into micro USD, rounded down in favour of the sale
+pub fn price_to_micro_usd(price: u64, expo: i32) -> Result<u64, ProgramError> {
+    let scale = expo + USD_DECIMALS as i32;
+
+    if scale.unsigned_abs() > u8::MAX as u32 {
+        return Err(ico_error(ICOError::PricingOverflow));
+    }
+
+    if scale >= 0 {
+        to_u64(mul_div(
+            price as u128,
+            token_unit(scale as u8)?,
+            1,
+            Rounding::Down,
+        )?)
+    } else {
+        to_u64(mul_div(
+            price as u128,
+            1,
+            token_unit((-scale) as u8)?,
+            Rounding::Down,
+        )?)
+    }
+}
+
+// Validates a Pyth price against the sale limits and returns it in micro USD
+pub fn validate_pyth_price(
+    pyth_price: &PythPrice,
+    clock: &Clock,
+    max_age_slots: u64,
+    max_age_seconds: u64,
+    max_confidence_bps: u16,
+) -> Result<u64, ProgramError> {
+    if pyth_price.status != PYTH_STATUS_TRADING {
+        return Err(ico_error(ICOError::OraclePriceNotTrading));
+    }
+
+    if pyth_price.price <= 0 {
+        return Err(ico_error(ICOError::InvalidOraclePrice));
+    }
+
+    let age_slots = clock.slot.saturating_sub(pyth_price.publish_slot);
+    let age_seconds = clock.unix_timestamp.saturating_sub(pyth_price.timestamp);
+
+    if (max_age_slots > 0 && age_slots > max_age_slots)
+        || (max_age_seconds > 0 && age_seconds > max_age_seconds as i64)
+    {
+        msg!(format!(
+            "Oracle price is {} slot(s) and {} second(s) old",
+            age_slots, age_seconds
+        )
+        .as_str());
+
+        return Err(ico_error(ICOError::OraclePriceStale));
+    }
+
+    let price = pyth_price.price as u64;
+    let confidence_bps = mul_div(pyth_price.conf as u128, 10_000, price as u128, Rounding::Up)?;
+
+    if confidence_bps > max_confidence_bps as u128 {
+        msg!(format!(
+            "Oracle price confidence interval is {} bps of the price",
+            confidence_bps
+        )
+        .as_str());
+
+        return Err(ico_error(ICOError::OraclePriceUncertain));
+    }
+
+    price_to_micro_usd(price, pyth_price.expo)
+}
+
+// Validates a program price feed quote age and returns it in micro USD
+pub fn validate_price_feed(
+    price_feed: &PriceFeed,
+    clock: &Clock,
+    max_age_seconds: u64,
+) -> Result<u64, ProgramError> {
+    if price_feed.price <= 0 {
+        return Err(ico_error(ICOError::InvalidOraclePrice));
+    }
+
+    let age_seconds = clock
+        .unix_timestamp
+        .saturating_sub(price_feed.publish_timestamp);
+
+    if age_seconds > max_age_seconds as i64 {
+        msg!(format!(
+            "Price feed quote is {} second(s) old, published at slot {}",
+            age_seconds, price_feed.publish_slot
+        )
+        .as_str());
+
+        return Err(ico_error(ICOError::OraclePriceStale));
+    }
+
+    price_to_micro_usd(price_feed.price as u64, price_feed.expo)
+}
+
+// Resolves the SOL/USD price in micro USD for an exchange, consuming the price
+// account from the instruction accounts when the sale reads it from an oracle
+pub fn load_sol_usd_price<'a, 'b: 'a, I: Iterator<Item = &'a AccountInfo<'b>>>(
+    program_id: &Pubkey,
+    config: &ICOSaleConfig,
+    accounts_iter: &mut I,
+) -> Result<u64, ProgramError> {
+    match &config.price_source {
+        SolPriceSource::Fixed => Ok(config.sol_usd),
+        SolPriceSource::Pyth {
+            price_account,
+            max_age_slots,
+            max_age_seconds,
+            max_confidence_bps,
+        } => {
+            let price_account_info = next_account_info(accounts_iter)?;
+
+            if price_account_info.key != price_account {
+                return Err(ico_error(ICOError::OraclePriceAccountMismatch));
+            }
+
+            let pyth_price = parse_pyth_price(&price_account_info.data.borrow())?;
+            let sol_usd = validate_pyth_price(
+                &pyth_price,
+                &Clock::get()?,
+                *max_age_slots,
+                *max_age_seconds,
+                *max_confidence_bps,
+            )?;
+
+            msg!(format!(
+                "Using SOL/USD price {} micro USD from Pyth account `{}`",
+                sol_usd, price_account
+            )
+            .as_str());
+
+            Ok(sol_usd)
+        }
+        SolPriceSource::PriceFeed {
+            publisher,
+            max_age_seconds,
+        } => {
+            let price_feed_account = next_account_info(accounts_iter)?;
+            let (price_feed_pda, _bump_seed) = find_price_feed_pda(program_id, publisher);
+
+            if price_feed_account.key != &price_feed_pda {
+                return Err(ico_error(ICOError::InvalidAddressPriceFeedPDA));
+            }
+
+            if price_feed_account.owner != program_id {
+                return Err(ico_error(ICOError::InvalidPriceFeedOwner));
+            }
+
+            let price_feed = PriceFeed::try_from_slice(&price_feed_account.data.borrow())?;
+            let sol_usd = validate_price_feed(&price_feed, &Clock::get()?, *max_age_seconds)?;
+
+            msg!(format!(
+                "Using SOL/USD price {} micro USD from price feed `{}`",
+                sol_usd, price_feed_account.key
+            )
+            .as_str());
+
+            Ok(sol_usd)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SOL at 150.00 USD with a 0.10 USD confidence interval, published at slot 1000
+    const PRICE: i64 = 15_000_000_000;
+    const CONF: u64 = 10_000_000;
+    const EXPO: i32 = -8;
+    const PUBLISH_SLOT: u64 = 1_000;
+    const PUBLISH_TIMESTAMP: i64 = 1_700_000_000;
+
+    fn write_bytes(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // Hand built Pyth v2 price account holding only the fields read by the program
+    fn pyth_account(price: i64, conf: u64, expo: i32, status: u32) -> Vec<u8> {
+        let mut data = vec![0u8; PYTH_PRICE_ACCOUNT_MIN_LEN];
+
+        write_bytes(&mut data, PYTH_MAGIC_OFFSET, &PYTH_MAGIC.to_le_bytes());
+        write_bytes(
+            &mut data,
+            PYTH_VERSION_OFFSET,
+            &PYTH_VERSION_2.to_le_bytes(),
+        );
+        write_bytes(
+            &mut data,
+            PYTH_ACCOUNT_TYPE_OFFSET,
+            &PYTH_ACCOUNT_TYPE_PRICE.to_le_bytes(),
+        );
+        write_bytes(&mut data, PYTH_EXPO_OFFSET, &expo.to_le_bytes());
+        write_bytes(
+            &mut data,
+            PYTH_TIMESTAMP_OFFSET,
+            &PUBLISH_TIMESTAMP.to_le_bytes(),
+        );
+        write_bytes(&mut data, PYTH_AGG_PRICE_OFFSET, &price.to_le_bytes());
+        write_bytes(&mut data, PYTH_AGG_CONF_OFFSET, &conf.to_le_bytes());
+        write_bytes(&mut data, PYTH_AGG_STATUS_OFFSET, &status.to_le_bytes());
+        write_bytes(
+            &mut data,
+            PYTH_AGG_PUB_SLOT_OFFSET,
+            &PUBLISH_SLOT.to_le_bytes(),
+        );
+
+        data
+    }
+
+    fn clock(slot: u64, unix_timestamp: i64) -> Clock {
+        Clock {
+            slot,
+            unix_timestamp,
+            ..Clock::default()
+        }
+    }
+
+    fn validate(data: &[u8], clock: &Clock) -> Result<u64, ProgramError> {
+        validate_pyth_price(&parse_pyth_price(data)?, clock, 25, 60, 50)
+    }
+
+    fn oracle_error(err: ICOError) -> ProgramError {
+        ProgramError::from(err)
+    }
+
+    #[test]
+    fn valid_price() {
+        let data = pyth_account(PRICE, CONF, EXPO, PYTH_STATUS_TRADING);
+
+        assert_eq!(
+            parse_pyth_price(&data).unwrap(),
+            PythPrice {
+                price: PRICE,
+                conf: CONF,
+                expo: EXPO,
+                status: PYTH_STATUS_TRADING,
+                publish_slot: PUBLISH_SLOT,
+                timestamp: PUBLISH_TIMESTAMP,
+            }
+        );
+        assert_eq!(
+            validate(&data, &clock(PUBLISH_SLOT + 25, PUBLISH_TIMESTAMP + 60)).unwrap(),
+            150_000_000
+        );
+    }
+
+    #[test]
+    fn invalid_account_header() {
+        let fixtures = [
+            (PYTH_MAGIC_OFFSET, 0xdead_beef_u32),
+            (PYTH_VERSION_OFFSET, 1),
+            (PYTH_ACCOUNT_TYPE_OFFSET, 2),
+        ];
+
+        for (offset, value) in fixtures {
+            let mut data = pyth_account(PRICE, CONF, EXPO, PYTH_STATUS_TRADING);
+            write_bytes(&mut data, offset, &value.to_le_bytes());
+
+            assert_eq!(
+                parse_pyth_price(&data).unwrap_err(),
+                oracle_error(ICOError::InvalidOraclePriceAccount)
+            );
+        }
+
+        let data = pyth_account(PRICE, CONF, EXPO, PYTH_STATUS_TRADING);
+
+        assert_eq!(
+            parse_pyth_price(&data[..PYTH_PRICE_ACCOUNT_MIN_LEN - 1]).unwrap_err(),
+            oracle_error(ICOError::InvalidOraclePriceAccount)
+        );
+    }
+
+    #[test]
+    fn price_not_trading() {
+        let data = pyth_account(PRICE, CONF, EXPO, 0);
+
+        assert_eq!(
+            validate(&data, &clock(PUBLISH_SLOT, PUBLISH_TIMESTAMP)).unwrap_err(),
+            oracle_error(ICOError::OraclePriceNotTrading)
+        );
+    }
+
+    #[test]
+    fn stale_price() {
+        let data = pyth_account(PRICE, CONF, EXPO, PYTH_STATUS_TRADING);
+
+        assert_eq!(
+            validate(&data, &clock(PUBLISH_SLOT + 26, PUBLISH_TIMESTAMP)).unwrap_err(),
+            oracle_error(ICOError::OraclePriceStale)
+        );
+        assert_eq!(
+            validate(&data, &clock(PUBLISH_SLOT, PUBLISH_TIMESTAMP + 61)).unwrap_err(),
+            oracle_error(ICOError::OraclePriceStale)
+        );
+
+        // A zero max age disables the corresponding check
+        let pyth_price = parse_pyth_price(&data).unwrap();
+        let clock = clock(PUBLISH_SLOT + 1_000, PUBLISH_TIMESTAMP + 30);
+
+        assert_eq!(
+            validate_pyth_price(&pyth_price, &clock, 0, 60, 50).unwrap(),
+            150_000_000
+        );
+    }
+
+    #[test]
+    fn uncertain_price() {
+        let clock = clock(PUBLISH_SLOT, PUBLISH_TIMESTAMP);
+
+        // 50 bps of the price is allowed, one more unit of confidence is not
+        let data = pyth_account(PRICE, 75_000_000, EXPO, PYTH_STATUS_TRADING);
+        assert_eq!(validate(&data, &clock).unwrap(), 150_000_000);
+
+        let data = pyth_account(PRICE, 75_000_001, EXPO, PYTH_STATUS_TRADING);
+        assert_eq!(
+            validate(&data, &clock).unwrap_err(),
+            oracle_error(ICOError::OraclePriceUncertain)
+        );
+    }
+
+    #[test]
+    fn non_positive_price() {
+        let clock = clock(PUBLISH_SLOT, PUBLISH_TIMESTAMP);
+
+        for price in [-PRICE, 0] {
+            let data = pyth_account(price, CONF, EXPO, PYTH_STATUS_TRADING);
+
+            assert_eq!(
+                validate(&data, &clock).unwrap_err(),
+                oracle_error(ICOError::InvalidOraclePrice)
+            );
+        }
+    }
+
+    #[test]
+    fn exponent_scaling() {
+        // Exponents below micro USD precision are rounded down
+        assert_eq!(price_to_micro_usd(15_000_000_099, -8).unwrap(), 150_000_000);
+        assert_eq!(price_to_micro_usd(150_000_000, -6).unwrap(), 150_000_000);
+        assert_eq!(price_to_micro_usd(15_000, -2).unwrap(), 150_000_000);
+        assert_eq!(price_to_micro_usd(150, 0).unwrap(), 150_000_000);
+        assert_eq!(price_to_micro_usd(15, 1).unwrap(), 150_000_000);
+
+        assert_eq!(
+            price_to_micro_usd(1, 300).unwrap_err(),
+            oracle_error(ICOError::PricingOverflow)
+        );
+        assert_eq!(
+            price_to_micro_usd(u64::MAX, 20).unwrap_err(),
+            oracle_error(ICOError::PricingOverflow)
+        );
+    }
+
+    #[test]
+    fn price_feed_quote() {
+        let price_feed = PriceFeed {
+            publisher: Pubkey::new_unique(),
+            price: 15_000,
+            expo: -2,
+            publish_slot: PUBLISH_SLOT,
+            publish_timestamp: PUBLISH_TIMESTAMP,
+        };
+
+        assert_eq!(
+            validate_price_feed(&price_feed, &clock(0, PUBLISH_TIMESTAMP + 60), 60).unwrap(),
+            150_000_000
+        );
+        assert_eq!(
+            validate_price_feed(&price_feed, &clock(0, PUBLISH_TIMESTAMP + 61), 60).unwrap_err(),
+            oracle_error(ICOError::OraclePriceStale)
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use solana_program::{native_token::LAMPORTS_PER_SOL, program_error::ProgramError};
+
+use crate::error::{ico_error, ICOError};
+
+// USD values are handled as integer micro USD: 1 USD = 1_000_000 micro USD
+pub const USD_DECIMALS: u8 = 6;
+
+// Price of one USD in micro USD, to handle USD values as a payment currency
+pub const ONE_USD: u64 = 1_000_000;
+
+// Lamports are the base units of SOL
+pub const SOL_DECIMALS: u8 = 9;
+
+// Rounding applied to integer divisions, amounts paid out by the sale are always
+// rounded down and amounts charged by the sale are always rounded up
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+// Computes `a * b / denominator` without intermediate overflow for u64 inputs
+pub fn mul_div(
+    a: u128,
+    b: u128,
+    denominator: u128,
+    rounding: Rounding,
+) -> Result<u128, ProgramError> {
+    if denominator == 0 {
+        return Err(ico_error(ICOError::PricingDivisionByZero));
+    }
+
+    let product = a
+        .checked_mul(b)
+        .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+    let quotient = product / denominator;
+
+    if rounding == Rounding::Up && product % denominator != 0 {
+        return quotient
+            .checked_add(1)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow));
+    }
+
+    Ok(quotient)
+}
+
+pub fn to_u64(value: u128) -> Result<u64, ProgramError> {
+    u64::try_from(value).map_err(|_| ico_error(ICOError::PricingOverflow))
+}
+
+// Amount of base units in one whole token for the given mint decimals
+pub fn token_unit(decimals: u8) -> Result<u128, ProgramError> {
+    10u128
+        .checked_pow(decimals as u32)
+        .ok_or_else(|| ico_error(ICOError::PricingOverflow))
+}
+
+// Value in micro USD of a lamports amount at `sol_usd` micro USD per SOL
+pub fn lamports_to_usd(
+    lamports: u64,
+    sol_usd: u64,
+    rounding: Rounding,
+) -> Result<u64, ProgramError> {
+    to_u64(mul_div(
+        lamports as u128,
+        sol_usd as u128,
+        LAMPORTS_PER_SOL as u128,
+        rounding,
+    )?)
+}
+
+// Lamports worth `usd` micro USD at `sol_usd` micro USD per SOL
+pub fn usd_to_lamports(usd: u64, sol_usd: u64, rounding: Rounding) -> Result<u64, ProgramError> {
+    to_u64(mul_div(
+        usd as u128,
+        LAMPORTS_PER_SOL as u128,
+        sol_usd as u128,
+        rounding,
+    )?)
+}
+
+// Value in micro USD of a token amount in base units at `token_usd` micro USD per token
+pub fn tokens_to_usd(
+    tokens: u64,
+    token_usd: u64,
+    decimals: u8,
+    rounding: Rounding,
+) -> Result<u64, ProgramError> {
+    to_u64(mul_div(
+        tokens as u128,
+        token_usd as u128,
+        token_unit(decimals)?,
+        rounding,
+    )?)
+}
+
+// Token base units worth `usd` micro USD at `token_usd` micro USD per token
+pub fn usd_to_tokens(
+    usd: u64,
+    token_usd: u64,
+    decimals: u8,
+    rounding: Rounding,
+) -> Result<u64, ProgramError> {
+    to_u64(mul_div(
+        usd as u128,
+        token_unit(decimals)?,
+        token_usd as u128,
+        rounding,
+    )?)
+}
+
+// Token base units bought by a payment in another currency (SOL or a quote token) worth
+// `payment_usd` micro USD per whole unit, in a single division so only one rounding step
+// (down, in favour of the sale) is applied
+pub fn payment_to_tokens(
+    payment: u64,
+    payment_usd: u64,
+    payment_decimals: u8,
+    token_usd: u64,
+    decimals: u8,
+) -> Result<u64, ProgramError> {
+    let numerator = (payment as u128)
+        .checked_mul(payment_usd as u128)
+        .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+    let denominator = token_unit(payment_decimals)?
+        .checked_mul(token_usd as u128)
+        .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+    to_u64(mul_div(
+        numerator,
+        token_unit(decimals)?,
+        denominator,
+        Rounding::Down,
+    )?)
+}
+
+// Payment base units owed for a token amount, rounded up in favour of the sale
+pub fn tokens_to_payment(
+    tokens: u64,
+    token_usd: u64,
+    decimals: u8,
+    payment_usd: u64,
+    payment_decimals: u8,
+) -> Result<u64, ProgramError> {
+    let numerator = (tokens as u128)
+        .checked_mul(token_usd as u128)
+        .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+    let denominator = token_unit(decimals)?
+        .checked_mul(payment_usd as u128)
+        .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+    to_u64(mul_div(
+        numerator,
+        token_unit(payment_decimals)?,
+        denominator,
+        Rounding::Up,
+    )?)
+}
+
+// Token base units bought by a lamports amount, rounded down in favour of the sale
+pub fn lamports_to_tokens(
+    lamports: u64,
+    sol_usd: u64,
+    token_usd: u64,
+    decimals: u8,
+) -> Result<u64, ProgramError> {
+    payment_to_tokens(lamports, sol_usd, SOL_DECIMALS, token_usd, decimals)
+}
+
+// Lamports owed for a token amount in base units, rounded up in favour of the sale
+pub fn tokens_to_lamports(
+    tokens: u64,
+    sol_usd: u64,
+    token_usd: u64,
+    decimals: u8,
+) -> Result<u64, ProgramError> {
+    tokens_to_payment(tokens, token_usd, decimals, sol_usd, SOL_DECIMALS)
+}
+
+// Formats an integer amount with the given decimals for logging, e.g. 1500000 (6) -> "1.500000"
+pub fn format_amount(amount: u64, decimals: u8) -> String {
+    match 10u128.checked_pow(decimals as u32) {
+        Some(unit) if decimals > 0 => format!(
+            "{}.{:0width$}",
+            amount as u128 / unit,
+            amount as u128 % unit,
+            width = decimals as usize
+        ),
+        _ => amount.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pricing_error(err: ICOError) -> ProgramError {
+        ProgramError::from(err)
+    }
+
+    #[test]
+    fn mul_div_exact_division_ignores_rounding() {
+        assert_eq!(mul_div(6, 4, 8, Rounding::Down).unwrap(), 3);
+        assert_eq!(mul_div(6, 4, 8, Rounding::Up).unwrap(), 3);
+    }
+
+    #[test]
+    fn mul_div_inexact_division_rounds() {
+        assert_eq!(mul_div(7, 3, 4, Rounding::Down).unwrap(), 5);
+        assert_eq!(mul_div(7, 3, 4, Rounding::Up).unwrap(), 6);
+        assert_eq!(mul_div(1, 1, 3, Rounding::Down).unwrap(), 0);
+        assert_eq!(mul_div(1, 1, 3, Rounding::Up).unwrap(), 1);
+    }
+
+    #[test]
+    fn mul_div_keeps_u64_products_exact() {
+        let max = u64::MAX as u128;
+
+        assert_eq!(mul_div(max, max, max, Rounding::Down).unwrap(), max);
+        assert_eq!(mul_div(max, max - 1, max, Rounding::Up).unwrap(), max - 1);
+    }
+
+    #[test]
+    fn mul_div_overflow() {
+        assert_eq!(
+            mul_div(u128::MAX, 2, 2, Rounding::Down).unwrap_err(),
+            pricing_error(ICOError::PricingOverflow)
+        );
+        assert_eq!(
+            mul_div(u128::MAX, 1, 2, Rounding::Up).unwrap(),
+            u128::MAX / 2 + 1
+        );
+    }
+
+    #[test]
+    fn mul_div_division_by_zero() {
+        assert_eq!(
+            mul_div(1, 1, 0, Rounding::Down).unwrap_err(),
+            pricing_error(ICOError::PricingDivisionByZero)
+        );
+        assert_eq!(
+            mul_div(0, 0, 0, Rounding::Up).unwrap_err(),
+            pricing_error(ICOError::PricingDivisionByZero)
+        );
+    }
+
+    #[test]
+    fn to_u64_overflow() {
+        assert_eq!(to_u64(u64::MAX as u128).unwrap(), u64::MAX);
+        assert_eq!(
+            to_u64(u64::MAX as u128 + 1).unwrap_err(),
+            pricing_error(ICOError::PricingOverflow)
+        );
+    }
+
+    #[test]
+    fn token_conversions_round_in_favour_of_the_sale() {
+        // 1 SOL at 150 USD buys 0.02 USD tokens with 9 decimals: 7500 whole tokens
+        assert_eq!(
+            lamports_to_tokens(1_000_000_000, 150_000_000, 20_000, 9).unwrap(),
+            7_500_000_000_000
+        );
+
+        // One lamport buys 7 base units rounded down, while 8 base units cost 2 lamports
+        // rounded up
+        assert_eq!(lamports_to_tokens(1, 150_000_000, 20_000, 6).unwrap(), 7);
+        assert_eq!(tokens_to_lamports(1, 150_000_000, 20_000, 6).unwrap(), 1);
+        assert_eq!(tokens_to_lamports(7, 150_000_000, 20_000, 6).unwrap(), 1);
+        assert_eq!(tokens_to_lamports(8, 150_000_000, 20_000, 6).unwrap(), 2);
+    }
+}
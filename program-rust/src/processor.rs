@@ -1,33 +1,48 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    clock::Clock,
     entrypoint::ProgramResult,
+    instruction::Instruction,
     msg,
-    native_token::LAMPORTS_PER_SOL,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
     program_pack::Pack,
     pubkey::Pubkey,
     rent::Rent,
-    system_instruction::transfer,
+    system_instruction::{self, transfer},
     sysvar::Sysvar,
 };
 
 use spl_token::state::{Account as TokenAccount, Mint};
 
-use crate::error::{ico_err, ICOError};
+use crate::error::{ico_err, ico_error, ICOError};
 
 use crate::config::{
-    CLASH_PAYMENT_AUTHORITY, CLASH_SOL_WALLET, CLASH_TOKEN_ID, CLASH_USD, MAX_USD_PRICE,
-    MIN_USD_PRICE, PROGRAM_PDA_SEED1, PROGRAM_PDA_SEED2, SOL_USD,
+    BUYER_RECORD_SEED, PRICE_FEED_SEED, PROGRAM_PDA_SEED1, PROGRAM_PDA_SEED2, VAULT_SEED,
 };
 
-use crate::state::{ClashTokenExchangeData, ClashTokenPaymentData, ICOProgramData};
+use crate::state::{
+    AllowlistProof, BidCommitData, BidRevealData, BuyerRecord, ClashTokenExactOutData,
+    ClashTokenExchangeData, ClashTokenPaymentData, ClashTokenSPLExchangeData, ExchangeOrder,
+    FairLaunchCommitData, ICOProgramData, ICOSaleConfig, PaymentCurrency, PriceFeed,
+    PriceFeedUpdateData, SaleStatus, SealedBid,
+};
 
 use crate::instruction::ProgramInstruction;
 
-use crate::util::{validate_account, validate_token_account};
+use crate::oracle::load_sol_usd_price;
+
+use crate::pricing::{format_amount, tokens_to_usd, Rounding, ONE_USD, SOL_DECIMALS, USD_DECIMALS};
+
+use crate::schedule::{auction_rebate_weight, PriceSchedule};
+
+use crate::util::{
+    bid_commitment, find_buyer_record_pda, find_price_feed_pda, find_sale_pda, find_vault_pda,
+    log_sale_config_changes, validate_account, validate_sale_config, validate_sale_config_update,
+    validate_sale_window, validate_token_account, verify_allowlist_proof,
+};
 
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::BorshSerialize;
 
 pub struct Processor;
 
@@ -40,21 +55,77 @@ impl Processor {
         let instruction = ProgramInstruction::unpack(instruction_data)?;
 
         match instruction {
-            ProgramInstruction::InitializeICO => {
+            ProgramInstruction::InitializeICO { sale_id, data } => {
                 msg!("Instruction: Initialize Clash ICO");
-                Self::initialize_ico(program_id, accounts)
+                Self::initialize_ico(program_id, accounts, sale_id, &data)
             }
-            ProgramInstruction::ExchangeClashToken { data } => {
+            ProgramInstruction::ExchangeClashToken { sale_id, data } => {
                 msg!("Instruction: Exchange Clash Token");
-                Self::exchange_clash_token(program_id, accounts, &data)
+                Self::exchange_clash_token(program_id, accounts, sale_id, &data)
             }
-            ProgramInstruction::ExecuteClashPayment { data } => {
+            ProgramInstruction::ExecuteClashPayment { sale_id, data } => {
                 msg!("Instruction: Execute Clash Payment");
-                Self::execute_clash_payment(program_id, accounts, &data)
+                Self::execute_clash_payment(program_id, accounts, sale_id, &data)
             }
-            ProgramInstruction::TerminateICO => {
+            ProgramInstruction::TerminateICO { sale_id } => {
                 msg!("Instruction: Terminate Clash ICO");
-                Self::terminate_ico(program_id, accounts)
+                Self::terminate_ico(program_id, accounts, sale_id)
+            }
+            ProgramInstruction::UpdateSaleConfig { sale_id, data } => {
+                msg!("Instruction: Update Clash ICO Sale Config");
+                Self::update_sale_config(program_id, accounts, sale_id, &data)
+            }
+            ProgramInstruction::ExchangeClashTokenExactOut { sale_id, data } => {
+                msg!("Instruction: Exchange Clash Token Exact Out");
+                Self::exchange_clash_token_exact_out(program_id, accounts, sale_id, &data)
+            }
+            ProgramInstruction::ExchangeClashTokenWithSPL { sale_id, data } => {
+                msg!("Instruction: Exchange Clash Token With SPL");
+                Self::exchange_clash_token_with_spl(program_id, accounts, sale_id, &data)
+            }
+            ProgramInstruction::PauseSale { sale_id } => {
+                msg!("Instruction: Pause Clash ICO");
+                Self::pause_sale(program_id, accounts, sale_id)
+            }
+            ProgramInstruction::ResumeSale { sale_id } => {
+                msg!("Instruction: Resume Clash ICO");
+                Self::resume_sale(program_id, accounts, sale_id)
+            }
+            ProgramInstruction::FinalizeSale { sale_id } => {
+                msg!("Instruction: Finalize Clash ICO");
+                Self::finalize_sale(program_id, accounts, sale_id)
+            }
+            ProgramInstruction::RefundPurchase { sale_id } => {
+                msg!("Instruction: Refund Clash ICO Purchase");
+                Self::refund_purchase(program_id, accounts, sale_id)
+            }
+            ProgramInstruction::ClaimTokens { sale_id } => {
+                msg!("Instruction: Claim Clash Tokens");
+                Self::claim_tokens(program_id, accounts, sale_id)
+            }
+            ProgramInstruction::ClaimRebate { sale_id } => {
+                msg!("Instruction: Claim Dutch Auction Rebate");
+                Self::claim_rebate(program_id, accounts, sale_id)
+            }
+            ProgramInstruction::CommitFairLaunch { sale_id, data } => {
+                msg!("Instruction: Commit SOL to Fair Launch");
+                Self::commit_fair_launch(program_id, accounts, sale_id, &data)
+            }
+            ProgramInstruction::PlaceBid { sale_id, data } => {
+                msg!("Instruction: Place Sealed Bid");
+                Self::place_bid(program_id, accounts, sale_id, &data)
+            }
+            ProgramInstruction::RevealBid { sale_id, data } => {
+                msg!("Instruction: Reveal Sealed Bid");
+                Self::reveal_bid(program_id, accounts, sale_id, &data)
+            }
+            ProgramInstruction::SettleBid { sale_id } => {
+                msg!("Instruction: Settle Batch Auction Bid");
+                Self::settle_bid(program_id, accounts, sale_id)
+            }
+            ProgramInstruction::UpdatePrice { data } => {
+                msg!("Instruction: Update SOL/USD Price Feed");
+                Self::update_price(program_id, accounts, &data)
             }
             ProgramInstruction::InvalidInstruction => {
                 msg!("Invalid instruction");
@@ -65,7 +136,12 @@ impl Processor {
         Ok(())
     }
 
-    pub fn initialize_ico(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    pub fn initialize_ico(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        data: &ICOSaleConfig,
+    ) -> ProgramResult {
         msg!("Initializing Clash ICO accounts and data");
 
         // Get accounts
@@ -92,26 +168,39 @@ impl Processor {
         validate_account(program_pda_account, false, true, false)?;
         validate_account(program_token_account, false, true, false)?;
 
+        validate_sale_config(data)?;
+
+        // Any SPL mint can be sold, the one used here is recorded in the sale state
+        if clash_token_account.owner != &spl_token::id() {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
         let initializer_associated_token_account =
             TokenAccount::unpack_unchecked(&initializer_token_account.data.borrow())?;
 
         validate_token_account(
             &initializer_associated_token_account,
             initializer_account.key,
-            &CLASH_TOKEN_ID,
+            clash_token_account.key,
         )?;
 
-        let (program_pda, bump_seed) =
-            Pubkey::find_program_address(&[PROGRAM_PDA_SEED1, PROGRAM_PDA_SEED2], program_id);
+        let (program_pda, bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
 
-        let program_signature = &[&PROGRAM_PDA_SEED1, &PROGRAM_PDA_SEED2, &[bump_seed][..]];
+        let sale_id_seed = sale_id.to_le_bytes();
+        let program_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            &sale_id_seed[..],
+            clash_token_account.key.as_ref(),
+            &[bump_seed][..],
+        ];
 
         if program_pda_account.key != &program_pda {
             ico_err(ICOError::InvalidAddressProgramPDA)?;
         };
 
-        if program_pda_account.lamports() != 0 {
-            let ico_data = ICOProgramData::try_from_slice(&program_pda_account.data.borrow())?;
+        if program_pda_account.owner == program_id {
+            let ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
             msg!("ICO was already initialized by `{}`", ico_data.initializer);
 
             ico_err(ICOError::AlreadyCreatedPDAAccount)?;
@@ -127,34 +216,20 @@ impl Processor {
         }
 
         msg!(format!(
-            "Creating ICO data account(PDA): `{}`\nInitializer: `{}`",
-            program_pda_account.key, initializer_account.key
+            "Creating ICO data account(PDA): `{}`\nSale ID: {}\nMint: `{}`\nInitializer: `{}`",
+            program_pda_account.key, sale_id, clash_token_account.key, initializer_account.key
         )
         .as_str());
 
-        let data_size = std::mem::size_of::<ICOProgramData>();
-
-        // Calculate minimum rent to make this account rent-exempt
-        // Lamports will be transferred back to owner account once this account is closed
-        let rent_sysvar = Rent::get()?;
-        let lamports_amount = rent_sysvar.minimum_balance(data_size);
-
-        let create_instruction = solana_program::system_instruction::create_account(
-            initializer_account.key,
-            program_pda_account.key,
-            lamports_amount,
-            data_size as u64,
+        // The account is rent-exempt, its lamports are transferred back to the initializer
+        // once it is closed
+        Self::create_pda_account(
             program_id,
-        );
-
-        invoke_signed(
-            &create_instruction,
-            &[
-                initializer_account.clone(),
-                program_pda_account.clone(),
-                system_program_account.clone(),
-            ],
-            &[&program_signature[..]],
+            program_pda_account,
+            initializer_account,
+            system_program_account,
+            ICOProgramData::LEN,
+            program_signature,
         )?;
 
         if program_token_account.lamports() == 0 {
@@ -190,17 +265,22 @@ impl Processor {
             .as_str());
         }
 
-        // Update ICO data with initializer information
-        let mut ico_data = ICOProgramData::try_from_slice(&program_pda_account.data.borrow())?;
+        // Update ICO data with initializer information and sale parameters
+        let mut ico_data = ICOProgramData::new(
+            *initializer_account.key,
+            *initializer_token_account.key,
+            sale_id,
+            *clash_token_account.key,
+            data.clone(),
+        );
 
-        ico_data.initializer = *initializer_account.key;
-        ico_data.initializer_ata = *initializer_token_account.key;
+        ico_data.sync_status(Clock::get()?.unix_timestamp)?;
 
         ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
 
         msg!(format!(
-            "Clash ICO program initialized by `{}`.",
-            initializer_account.key
+            "Clash ICO program initialized by `{}`.\nSale config: {:?}",
+            initializer_account.key, ico_data.config
         )
         .as_str());
 
@@ -210,16 +290,95 @@ impl Processor {
     pub fn exchange_clash_token(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
+        sale_id: u64,
         data: &ClashTokenExchangeData,
     ) -> ProgramResult {
         msg!("Processing exchange SOL by CLASH tokens instruction.");
 
+        let order = ExchangeOrder::ExactIn {
+            amount_in: data.sol_as_lamports_amount,
+            min_clash_out: data.min_clash_out,
+            allow_partial_fill: data.allow_partial_fill,
+        };
+
+        Self::process_exchange(
+            program_id,
+            accounts,
+            sale_id,
+            order,
+            data.expiry_slot,
+            PaymentCurrency::Sol,
+            data.allowlist_proof.as_ref(),
+        )
+    }
+
+    pub fn exchange_clash_token_exact_out(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        data: &ClashTokenExactOutData,
+    ) -> ProgramResult {
+        msg!("Processing exchange SOL by an exact amount of CLASH tokens instruction.");
+
+        let order = ExchangeOrder::ExactOut {
+            clash_out: data.clash_token_amount,
+            max_amount_in: data.max_lamports_in,
+        };
+
+        Self::process_exchange(
+            program_id,
+            accounts,
+            sale_id,
+            order,
+            data.expiry_slot,
+            PaymentCurrency::Sol,
+            data.allowlist_proof.as_ref(),
+        )
+    }
+
+    pub fn exchange_clash_token_with_spl(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        data: &ClashTokenSPLExchangeData,
+    ) -> ProgramResult {
+        msg!("Processing exchange SPL quote tokens by CLASH tokens instruction.");
+
+        let order = ExchangeOrder::ExactIn {
+            amount_in: data.quote_amount,
+            min_clash_out: data.min_clash_out,
+            allow_partial_fill: false,
+        };
+
+        Self::process_exchange(
+            program_id,
+            accounts,
+            sale_id,
+            order,
+            data.expiry_slot,
+            PaymentCurrency::Spl,
+            data.allowlist_proof.as_ref(),
+        )
+    }
+
+    // Shared by every exchange instruction, which only differ on the amount bound by the
+    // buyer and on the currency used to pay
+    fn process_exchange(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        order: ExchangeOrder,
+        expiry_slot: Option<u64>,
+        currency: PaymentCurrency,
+        allowlist_proof: Option<&AllowlistProof>,
+    ) -> ProgramResult {
         // Get accounts
         let accounts_iter = &mut accounts.iter();
 
         let from_sol_account = next_account_info(accounts_iter)?;
         let to_token_account = next_account_info(accounts_iter)?;
 
+        // SOL wallet or quote token account receiving the payment
         let to_sol_account = next_account_info(accounts_iter)?;
         let from_token_account = next_account_info(accounts_iter)?;
 
@@ -233,6 +392,9 @@ impl Processor {
         let associated_token_account_program = next_account_info(accounts_iter)?;
         let sysvar_rent_program_account = next_account_info(accounts_iter)?;
 
+        let buyer_record_account = next_account_info(accounts_iter)?;
+        let vault_account = next_account_info(accounts_iter)?;
+
         validate_account(from_sol_account, true, true, true)?;
         validate_account(to_token_account, false, true, false)?;
 
@@ -241,14 +403,14 @@ impl Processor {
 
         validate_account(clash_token_account, false, false, true)?;
 
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(buyer_record_account, false, true, false)?;
+        validate_account(vault_account, false, true, false)?;
+
         if program_account.key != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
 
-        if clash_token_account.key != &CLASH_TOKEN_ID {
-            ico_err(ICOError::InvalidClashTokenId)?;
-        }
-
         if from_sol_account.key == to_sol_account.key {
             ico_err(ICOError::CannotTransferSameAccount)?;
         }
@@ -257,8 +419,78 @@ impl Processor {
             ico_err(ICOError::CannotTransferSameAssociatedAccount)?;
         }
 
-        if to_sol_account.key != &CLASH_SOL_WALLET {
-            ico_err(ICOError::InvalidClashTokenDestinationWallet)?;
+        let (program_pda, bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        let sale_id_seed = sale_id.to_le_bytes();
+        let program_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            &sale_id_seed[..],
+            clash_token_account.key.as_ref(),
+            &[bump_seed][..],
+        ];
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        let clock = Clock::get()?;
+        Self::process_sale_window(program_pda_account, &mut ico_data, &clock)?;
+
+        let sale_config = &ico_data.config;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        if sale_config.fair_launch.is_some() {
+            ico_err(ICOError::FairLaunchCommitOnly)?;
+        }
+
+        if sale_config.batch_auction.is_some() {
+            ico_err(ICOError::BatchAuctionBidOnly)?;
+        }
+
+        // Soft cap sales escrow SOL and hold tokens until they are finalized, Dutch auctions
+        // with rebates escrow SOL until buyers claim their rebates and sales distributing
+        // tokens at the TGE hold them until then
+        let auction_price = ico_data.auction_price(clock.unix_timestamp);
+        let holds_tokens = ico_data.holds_tokens();
+        let escrowed = sale_config.soft_cap_usd > 0 || ico_data.has_auction_rebate();
+        let vests = sale_config.vesting.is_some();
+
+        if escrowed && currency != PaymentCurrency::Sol {
+            ico_err(ICOError::EscrowRequiresSOL)?;
+        }
+
+        // Buyers of a gated sale are bound by their allocation when the allowlist defines one
+        let mut wallet_cap = sale_config.max_usd_per_wallet;
+
+        if let Some(allowlist_root) = &sale_config.allowlist_root {
+            let allowlist_proof = match allowlist_proof {
+                Some(allowlist_proof)
+                    if verify_allowlist_proof(
+                        allowlist_root,
+                        from_sol_account.key,
+                        allowlist_proof,
+                    ) =>
+                {
+                    allowlist_proof
+                }
+                _ => return ico_err(ICOError::InvalidAllowlistProof),
+            };
+
+            if let Some(allocation_usd) = allowlist_proof.allocation_usd {
+                if wallet_cap == 0 || allocation_usd < wallet_cap {
+                    wallet_cap = allocation_usd;
+                }
+            }
         }
 
         if to_token_account.lamports() != 0 {
@@ -270,15 +502,6 @@ impl Processor {
             }
         }
 
-        let (program_pda, bump_seed) =
-            Pubkey::find_program_address(&[PROGRAM_PDA_SEED1, PROGRAM_PDA_SEED2], program_id);
-
-        let program_signature = &[&PROGRAM_PDA_SEED1, &PROGRAM_PDA_SEED2, &[bump_seed][..]];
-
-        if program_pda_account.key != &program_pda {
-            ico_err(ICOError::InvalidAddressProgramPDA)?;
-        }
-
         let from_associated_token_account =
             TokenAccount::unpack_unchecked(&from_token_account.data.borrow())?;
 
@@ -286,101 +509,415 @@ impl Processor {
             ico_err(ICOError::InvalidProgramAssociatedPDAOwner)?;
         }
 
-        // Calculate outcome value in Clash tokens based on SOL/USD price
-        let lamports_amount = data.sol_as_lamports_amount;
-        let sol_amount = lamports_amount as f64 / LAMPORTS_PER_SOL as f64;
+        if let Some(expiry_slot) = expiry_slot {
+            if clock.slot > expiry_slot {
+                msg!(format!(
+                    "Exchange expired at slot {}, current slot is {}",
+                    expiry_slot, clock.slot
+                )
+                .as_str());
+
+                ico_err(ICOError::ExchangeDeadlineExpired)?;
+            }
+        }
+
+        // Resolve the USD price of the payment currency, quote token payments are made
+        // from a buyer token account into a `clash_sol_wallet` token account of the same mint
+        let (payment_usd, payment_decimals, quote_accounts) = match currency {
+            PaymentCurrency::Sol => {
+                if to_sol_account.key != &sale_config.clash_sol_wallet {
+                    ico_err(ICOError::InvalidClashTokenDestinationWallet)?;
+                }
+
+                let sol_usd = load_sol_usd_price(program_id, sale_config, accounts_iter)?;
+
+                (sol_usd, SOL_DECIMALS, None)
+            }
+            PaymentCurrency::Spl => {
+                let from_quote_account = next_account_info(accounts_iter)?;
+                let quote_mint_account = next_account_info(accounts_iter)?;
+
+                validate_account(from_quote_account, false, true, true)?;
+                validate_account(quote_mint_account, false, false, true)?;
+
+                let quote_mint_config = match sale_config
+                    .accepted_quote_mints
+                    .iter()
+                    .find(|quote_mint| &quote_mint.mint == quote_mint_account.key)
+                {
+                    Some(quote_mint_config) => quote_mint_config,
+                    None => return ico_err(ICOError::QuoteMintNotAccepted),
+                };
+
+                if quote_mint_account.owner != token_program_account.key {
+                    return Err(ProgramError::IncorrectProgramId);
+                }
+
+                validate_token_account(
+                    &TokenAccount::unpack(&to_sol_account.data.borrow())?,
+                    &sale_config.clash_sol_wallet,
+                    quote_mint_account.key,
+                )?;
+
+                validate_token_account(
+                    &TokenAccount::unpack(&from_quote_account.data.borrow())?,
+                    from_sol_account.key,
+                    quote_mint_account.key,
+                )?;
+
+                let quote_mint_data = Mint::unpack(&quote_mint_account.data.borrow())?;
+
+                (
+                    quote_mint_config.usd_price,
+                    quote_mint_data.decimals,
+                    Some((from_quote_account, quote_mint_account)),
+                )
+            }
+        };
+
+        // Calculate exchanged amounts based on the payment currency USD price
+        let clash_mint_data = Mint::unpack_unchecked(&clash_token_account.data.borrow())?;
+        let clash_decimals = clash_mint_data.decimals;
+
+        let schedule = PriceSchedule::load(&ico_data, clock.unix_timestamp)?;
+
+        // Tiered sales may run out of open rounds before the whole exchange is filled
+        let (mut payment_amount, mut clash_amount_final) = match order {
+            ExchangeOrder::ExactIn {
+                amount_in,
+                allow_partial_fill,
+                ..
+            } => {
+                let fill = schedule.fill_exact_in(
+                    amount_in,
+                    payment_usd,
+                    payment_decimals,
+                    clash_decimals,
+                )?;
+
+                if fill.payment < amount_in {
+                    if !allow_partial_fill {
+                        ico_err(ICOError::InsuficientClashToken)?;
+                    }
+
+                    msg!(format!(
+                        "Open price rounds partially fill the exchange: {} of {} payment base units",
+                        fill.payment, amount_in
+                    )
+                    .as_str());
+                }
+
+                (fill.payment, fill.clash)
+            }
+            ExchangeOrder::ExactOut { clash_out, .. } => {
+                let fill = schedule.fill_exact_out(
+                    clash_out,
+                    payment_usd,
+                    payment_decimals,
+                    clash_decimals,
+                )?;
+
+                if fill.clash < clash_out {
+                    ico_err(ICOError::InsuficientClashToken)?;
+                }
+
+                (fill.payment, fill.clash)
+            }
+        };
 
-        let usd_amount = sol_amount * SOL_USD;
-        let clash_amount = usd_amount / CLASH_USD;
+        // USD limits always apply to the payment value actually charged
+        let mut usd_amount = tokens_to_usd(
+            payment_amount,
+            payment_usd,
+            payment_decimals,
+            Rounding::Down,
+        )?;
 
-        if usd_amount < MIN_USD_PRICE {
+        if usd_amount < sale_config.min_usd_price {
             ico_err(ICOError::InvalidOfferTooFew)?;
         }
 
-        if usd_amount > MAX_USD_PRICE {
+        if usd_amount > sale_config.max_usd_price {
             ico_err(ICOError::InvalidOfferTooMuch)?;
         }
 
-        let clash_mint_data = Mint::unpack_unchecked(&clash_token_account.data.borrow())?;
-        let clash_decimals = clash_mint_data.decimals;
-
-        let clash_amount_final: u64 = if clash_decimals > 0 {
-            (clash_amount * (10u32.pow(clash_decimals as u32)) as f64) as u64
-        } else {
-            clash_amount as u64
+        // Payment and USD value charged when an exchange is partially filled
+        let partial_fill = |clash_amount: u64| {
+            schedule.partial_fill(clash_amount, payment_usd, payment_decimals, clash_decimals)
         };
 
+        // The purchase reaching the hard cap only buys the value left, exact out orders
+        // are filled entirely or rejected. That final fill may be worth less than
+        // `min_usd_price`, the sale ending once less than that minimum is left
+        if let Some(remaining_usd) = ico_data.remaining_usd() {
+            if usd_amount > remaining_usd {
+                if let ExchangeOrder::ExactOut { .. } = order {
+                    msg!(format!(
+                        "Exchange is worth {}USD, {}USD left before the sale hard cap",
+                        format_amount(usd_amount, USD_DECIMALS),
+                        format_amount(remaining_usd, USD_DECIMALS)
+                    )
+                    .as_str());
+
+                    ico_err(ICOError::HardCapExceeded)?;
+                }
+
+                clash_amount_final = schedule
+                    .fill_exact_in(remaining_usd, ONE_USD, USD_DECIMALS, clash_decimals)?
+                    .clash;
+
+                let (partial_payment_amount, partial_usd_amount) =
+                    partial_fill(clash_amount_final)?;
+
+                payment_amount = partial_payment_amount;
+                usd_amount = partial_usd_amount;
+
+                msg!(format!(
+                    "Sale hard cap partially fills the exchange: {} CLASH base units for {} payment base units",
+                    clash_amount_final, payment_amount
+                )
+                .as_str());
+            }
+        }
+
+        // Tokens claimable by buyers of a soft cap sale or vesting for them are not available
+        // anymore, exchanges opting in sell whatever is left
+        let available_clash = from_associated_token_account
+            .amount
+            .saturating_sub(ico_data.held_clash());
+
+        if available_clash < clash_amount_final {
+            match order {
+                ExchangeOrder::ExactIn {
+                    allow_partial_fill: true,
+                    ..
+                } if available_clash > 0 => {
+                    let (partial_payment_amount, partial_usd_amount) =
+                        partial_fill(available_clash)?;
+
+                    msg!(format!(
+                        "Partially filling the exchange: {} of {} CLASH base units for {} of {} payment base units",
+                        available_clash, clash_amount_final, partial_payment_amount, payment_amount
+                    )
+                    .as_str());
+
+                    clash_amount_final = available_clash;
+                    payment_amount = partial_payment_amount;
+                    usd_amount = partial_usd_amount;
+                }
+                _ => ico_err(ICOError::InsuficientClashToken)?,
+            }
+        }
+
         // Check exchange can proceed base on CLASH token amount calculated
         if clash_amount_final == 0 {
             ico_err(ICOError::InvalidClashTokenAmount)?;
         }
 
-        // Check for enough funds for both SOL and CLASH token wallets
-        if from_sol_account.lamports() <= lamports_amount {
+        match order {
+            ExchangeOrder::ExactIn { min_clash_out, .. } if clash_amount_final < min_clash_out => {
+                msg!(format!(
+                    "Exchange would return {} CLASH base units, minimum requested is {}",
+                    clash_amount_final, min_clash_out
+                )
+                .as_str());
+
+                ico_err(ICOError::ExchangeSlippageExceeded)?;
+            }
+            ExchangeOrder::ExactOut { max_amount_in, .. } if payment_amount > max_amount_in => {
+                msg!(format!(
+                    "Exchange would cost {} payment base units, maximum requested is {}",
+                    payment_amount, max_amount_in
+                )
+                .as_str());
+
+                ico_err(ICOError::ExchangeMaxInputExceeded)?;
+            }
+            _ => {}
+        }
+
+        // Check for enough funds for both payment and CLASH token wallets
+        let enough_funds = match quote_accounts {
+            None => from_sol_account.lamports() > payment_amount,
+            Some((from_quote_account, _)) => {
+                TokenAccount::unpack(&from_quote_account.data.borrow())?.amount >= payment_amount
+            }
+        };
+
+        if !enough_funds {
             return Err(ProgramError::InsufficientFunds);
         }
 
-        if from_associated_token_account.amount < clash_amount_final {
-            ico_err(ICOError::InsuficientClashToken)?;
+        // Track the purchase against the buyer lifetime cap
+        let (mut buyer_record, new_buyer) = Self::load_buyer_record(
+            program_id,
+            buyer_record_account,
+            from_sol_account,
+            system_program_account,
+            &program_pda,
+            from_sol_account.key,
+        )?;
+
+        let lamports_paid = match quote_accounts {
+            None => payment_amount,
+            Some(_) => 0,
+        };
+
+        buyer_record.add_purchase(lamports_paid, usd_amount, clash_amount_final, wallet_cap)?;
+
+        let escrowed_lamports = if escrowed { payment_amount } else { 0 };
+        let claimable_clash = if holds_tokens { clash_amount_final } else { 0 };
+        let rebate_weight = match auction_price {
+            Some(clash_usd) if ico_data.has_auction_rebate() => {
+                auction_rebate_weight(payment_amount, clash_usd)?
+            }
+            _ => 0,
+        };
+
+        buyer_record.add_escrow(escrowed_lamports, claimable_clash, rebate_weight)?;
+
+        if vests {
+            buyer_record.add_vesting(clash_amount_final)?;
         }
 
-        if to_token_account.lamports() == 0 {
-            msg!(format!(
-                "Creating ATA account `{}` because it does not exists yet",
-                to_token_account.key
-            )
-            .as_str());
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+
+        if !holds_tokens && !vests {
+            Self::load_token_account(
+                to_token_account,
+                from_sol_account,
+                from_sol_account,
+                clash_token_account,
+                system_program_account,
+                token_program_account,
+                associated_token_account_program,
+                sysvar_rent_program_account,
+            )?;
+        }
 
-            let create_ata_instruction =
-                &spl_associated_token_account::create_associated_token_account(
-                    from_sol_account.key,
+        let clash_amount = format_amount(clash_amount_final, clash_decimals);
+        let payment_symbol = match quote_accounts {
+            None => String::from("SOL"),
+            Some((_, quote_mint_account)) => format!("`{}`", quote_mint_account.key),
+        };
+
+        msg!(format!(
+            "Exchanging {} {} tokens({}USD) by {} CLASH tokens from account `{}` to `{}`",
+            format_amount(payment_amount, payment_decimals),
+            payment_symbol,
+            format_amount(usd_amount, USD_DECIMALS),
+            clash_amount,
+            from_sol_account.key,
+            to_sol_account.key
+        )
+        .as_str());
+
+        match quote_accounts {
+            None => {
+                // Transfer SOL as lamports to CLASH team account, or to the sale vault
+                // of sales escrowing it
+                let sol_destination_account = if escrowed {
+                    Self::load_vault(
+                        program_id,
+                        vault_account,
+                        from_sol_account,
+                        system_program_account,
+                        &program_pda,
+                    )?;
+
+                    vault_account
+                } else {
+                    to_sol_account
+                };
+
+                let transfer_instruction = transfer(
                     from_sol_account.key,
-                    clash_token_account.key,
+                    sol_destination_account.key,
+                    payment_amount,
                 );
 
-            invoke(
-                &create_ata_instruction,
-                &[
-                    from_sol_account.clone(),
-                    to_token_account.clone(),
-                    clash_token_account.clone(),
-                    system_program_account.clone(),
-                    associated_token_account_program.clone(),
-                    sysvar_rent_program_account.clone(),
-                ],
-            )?;
+                invoke(
+                    &transfer_instruction,
+                    &[
+                        from_sol_account.clone(),
+                        sol_destination_account.clone(),
+                        system_program_account.clone(),
+                    ],
+                )?;
+
+                msg!(format!(
+                    "Success transferred {} lamports from `{}` to `{}`.",
+                    payment_amount, from_sol_account.key, sol_destination_account.key
+                )
+                .as_str());
+            }
+            Some((from_quote_account, quote_mint_account)) => {
+                // Transfer quote tokens to CLASH team token account, signed by the buyer
+                let transfer_quote_instruction = spl_token::instruction::transfer_checked(
+                    token_program_account.key, // token_program_id: &Pubkey
+                    from_quote_account.key,    // source_pubkey: &Pubkey
+                    quote_mint_account.key,    // mint_pubkey: &Pubkey
+                    to_sol_account.key,        // destination_pubkey: &Pubkey
+                    from_sol_account.key,      // authority_pubkey: &Pubkey
+                    &[],                       // signer_pubkeys: &[&Pubkey]
+                    payment_amount,            // amount: u64
+                    payment_decimals,          // decimals: u8
+                )?;
+
+                invoke(
+                    &transfer_quote_instruction,
+                    &[
+                        from_quote_account.clone(),
+                        quote_mint_account.clone(),
+                        to_sol_account.clone(),
+                        from_sol_account.clone(),
+                        token_program_account.clone(),
+                    ],
+                )?;
+
+                msg!(format!(
+                    "Success transferred {} quote tokens from `{}` to `{}`.",
+                    format_amount(payment_amount, payment_decimals),
+                    from_quote_account.key,
+                    to_sol_account.key
+                )
+                .as_str());
+            }
+        }
 
+        ico_data.add_purchase(lamports_paid, usd_amount, clash_amount_final, new_buyer)?;
+        ico_data.advance_rounds(clash_amount_final, clock.unix_timestamp)?;
+        ico_data.add_escrow(escrowed_lamports, claimable_clash, rebate_weight)?;
+
+        if vests {
+            ico_data.add_vesting(clash_amount_final)?;
+        }
+
+        if let Some(clash_usd) = auction_price {
+            ico_data.record_auction_price(clash_usd);
+        }
+
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        if holds_tokens {
             msg!(format!(
-                "Success creating ATA account `{}` for account `{}`",
-                to_token_account.key, from_sol_account.key
+                "{} CLASH tokens held as claimable by `{}` until the sale is finalized or its TGE.",
+                clash_amount, from_sol_account.key
             )
             .as_str());
-        }
 
-        msg!(format!(
-            "Exchanging {} SOL tokens({}USD) by {} CLASH tokens from account `{}` to `{}`",
-            sol_amount, usd_amount, clash_amount, from_sol_account.key, to_sol_account.key
-        )
-        .as_str());
-
-        // Transfer SOL as lamports to CLASH team account
-        let transfer_instruction =
-            transfer(from_sol_account.key, to_sol_account.key, lamports_amount);
+            return Ok(());
+        }
 
-        invoke(
-            &transfer_instruction,
-            &[
-                from_sol_account.clone(),
-                to_sol_account.clone(),
-                system_program_account.clone(),
-            ],
-        )?;
+        if vests {
+            msg!(format!(
+                "{} CLASH tokens vesting for `{}`, claimable as they vest.",
+                clash_amount, from_sol_account.key
+            )
+            .as_str());
 
-        msg!(format!(
-            "Success transferred {} lamports from `{}` to `{}`.",
-            lamports_amount, from_sol_account.key, to_sol_account.key
-        )
-        .as_str());
+            return Ok(());
+        }
 
         // Transfer CLASH tokens from program ATA to account transferring SOL's
         let transfer_token_instruction = spl_token::instruction::transfer_checked(
@@ -394,7 +931,7 @@ impl Processor {
             clash_decimals,            // decimals: u8
         )?;
 
-        invoke_signed(
+        Self::invoke_sale_signed(
             &transfer_token_instruction,
             &[
                 from_sol_account.clone(),
@@ -405,7 +942,7 @@ impl Processor {
                 token_program_account.clone(),
                 program_pda_account.clone(),
             ],
-            &[&program_signature[..]],
+            &program_signature[..],
         )?;
 
         msg!(format!(
@@ -420,6 +957,7 @@ impl Processor {
     pub fn execute_clash_payment(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
+        sale_id: u64,
         data: &ClashTokenPaymentData,
     ) -> ProgramResult {
         msg!("Processing payment of CLASH tokens payed via Coinpayment");
@@ -443,30 +981,67 @@ impl Processor {
         let associated_token_account_program = next_account_info(accounts_iter)?;
         let sysvar_rent_program_account = next_account_info(accounts_iter)?;
 
+        let buyer_record_account = next_account_info(accounts_iter)?;
+
         validate_account(payer_account, false, false, true)?;
         validate_account(payer_token_account, false, true, false)?;
 
         validate_account(clash_token_account, false, false, true)?;
 
-        validate_account(trusted_signer_authority, true, false, true)?;
+        validate_account(trusted_signer_authority, true, true, true)?;
         validate_account(program_token_account, false, true, true)?;
 
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(buyer_record_account, false, true, false)?;
+
         if program_account.key != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
 
-        if clash_token_account.key != &CLASH_TOKEN_ID {
-            ico_err(ICOError::InvalidClashTokenId)?;
-        }
-
         if payer_token_account.key == program_token_account.key {
             ico_err(ICOError::CannotTransferSameAssociatedAccount)?;
         }
 
-        if trusted_signer_authority.key != &CLASH_PAYMENT_AUTHORITY {
+        let (program_pda, bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        let sale_id_seed = sale_id.to_le_bytes();
+        let program_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            &sale_id_seed[..],
+            clash_token_account.key.as_ref(),
+            &[bump_seed][..],
+        ];
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        let clock = Clock::get()?;
+        Self::process_sale_window(program_pda_account, &mut ico_data, &clock)?;
+
+        if trusted_signer_authority.key != &ico_data.config.clash_payment_authority {
             ico_err(ICOError::InvalidClashTrustedAuthority)?;
         }
 
+        if ico_data.config.fair_launch.is_some() {
+            ico_err(ICOError::FairLaunchCommitOnly)?;
+        }
+
+        if ico_data.config.batch_auction.is_some() {
+            ico_err(ICOError::BatchAuctionBidOnly)?;
+        }
+
         if payer_token_account.lamports() != 0 {
             let payer_associated_token_account =
                 TokenAccount::unpack_unchecked(&payer_token_account.data.borrow())?;
@@ -476,15 +1051,6 @@ impl Processor {
             }
         }
 
-        let (program_pda, bump_seed) =
-            Pubkey::find_program_address(&[PROGRAM_PDA_SEED1, PROGRAM_PDA_SEED2], program_id);
-
-        let program_signature = &[&PROGRAM_PDA_SEED1, &PROGRAM_PDA_SEED2, &[bump_seed][..]];
-
-        if program_pda_account.key != &program_pda {
-            ico_err(ICOError::InvalidAddressProgramPDA)?;
-        }
-
         let program_associated_token_account =
             TokenAccount::unpack_unchecked(&program_token_account.data.borrow())?;
 
@@ -500,45 +1066,122 @@ impl Processor {
         }
 
         // Check for enough funds for both SOL and CLASH token wallets
-        if program_associated_token_account.amount < clash_amount_final {
+        if program_associated_token_account
+            .amount
+            .saturating_sub(ico_data.held_clash())
+            < clash_amount_final
+        {
+            ico_err(ICOError::InsuficientClashToken)?;
+        }
+
+        let clash_mint_data = Mint::unpack_unchecked(&clash_token_account.data.borrow())?;
+        let clash_decimals = clash_mint_data.decimals;
+
+        // Payments realized off chain are priced against the open rounds of tiered sales
+        // and count towards the buyer lifetime cap as well
+        let fill = PriceSchedule::load(&ico_data, clock.unix_timestamp)?.fill_exact_out(
+            clash_amount_final,
+            ONE_USD,
+            USD_DECIMALS,
+            clash_decimals,
+        )?;
+
+        if fill.clash < clash_amount_final {
             ico_err(ICOError::InsuficientClashToken)?;
         }
 
-        if payer_token_account.lamports() == 0 {
+        let usd_amount = fill.payment;
+
+        // Payments realized off chain are not partially filled by the hard cap
+        if let Some(remaining_usd) = ico_data.remaining_usd() {
+            if usd_amount > remaining_usd {
+                msg!(format!(
+                    "Payment is worth {}USD, {}USD left before the sale hard cap",
+                    format_amount(usd_amount, USD_DECIMALS),
+                    format_amount(remaining_usd, USD_DECIMALS)
+                )
+                .as_str());
+
+                ico_err(ICOError::HardCapExceeded)?;
+            }
+        }
+
+        let (mut buyer_record, new_buyer) = Self::load_buyer_record(
+            program_id,
+            buyer_record_account,
+            trusted_signer_authority,
+            system_program_account,
+            &program_pda,
+            payer_account.key,
+        )?;
+
+        buyer_record.add_purchase(
+            0,
+            usd_amount,
+            clash_amount_final,
+            ico_data.config.max_usd_per_wallet,
+        )?;
+
+        // Soft cap and TGE sales hold tokens paid off chain as claimable too, payments realized
+        // off chain take part in the Dutch auction clearing price but get no rebate
+        let holds_tokens = ico_data.holds_tokens();
+        let claimable_clash = if holds_tokens { clash_amount_final } else { 0 };
+        let vests = ico_data.config.vesting.is_some();
+
+        buyer_record.add_escrow(0, claimable_clash, 0)?;
+
+        if vests {
+            buyer_record.add_vesting(clash_amount_final)?;
+        }
+
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+
+        ico_data.add_purchase(0, usd_amount, clash_amount_final, new_buyer)?;
+        ico_data.advance_rounds(clash_amount_final, clock.unix_timestamp)?;
+        ico_data.add_escrow(0, claimable_clash, 0)?;
+
+        if vests {
+            ico_data.add_vesting(clash_amount_final)?;
+        }
+
+        if let Some(clash_usd) = ico_data.auction_price(clock.unix_timestamp) {
+            ico_data.record_auction_price(clash_usd);
+        }
+
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        if holds_tokens {
             msg!(format!(
-                "Creating ATA account `{}` because it does not exists yet",
-                payer_token_account.key
+                "{} CLASH base units held as claimable by `{}` until the sale is finalized or its TGE.",
+                clash_amount_final, payer_account.key
             )
             .as_str());
 
-            let create_ata_instruction =
-                &spl_associated_token_account::create_associated_token_account(
-                    trusted_signer_authority.key,
-                    payer_account.key,
-                    clash_token_account.key,
-                );
-
-            invoke(
-                &create_ata_instruction,
-                &[
-                    trusted_signer_authority.clone(),
-                    payer_account.clone(),
-                    payer_token_account.clone(),
-                    clash_token_account.clone(),
-                    system_program_account.clone(),
-                    associated_token_account_program.clone(),
-                    sysvar_rent_program_account.clone(),
-                ],
-            )?;
+            return Ok(());
+        }
 
+        if vests {
             msg!(format!(
-                "Success creating ATA account `{}` for account `{}`",
-                payer_token_account.key, payer_account.key
+                "{} CLASH base units vesting for `{}`, claimable as they vest.",
+                clash_amount_final, payer_account.key
             )
             .as_str());
+
+            return Ok(());
         }
 
-        let clash_amount = clash_amount_final as f64 / LAMPORTS_PER_SOL as f64;
+        Self::load_token_account(
+            payer_token_account,
+            payer_account,
+            trusted_signer_authority,
+            clash_token_account,
+            system_program_account,
+            token_program_account,
+            associated_token_account_program,
+            sysvar_rent_program_account,
+        )?;
+
+        let clash_amount = format_amount(clash_amount_final, clash_decimals);
 
         msg!(format!(
             "Transferring {} CLASH tokens to account `{}` for its payment via CoinPayment",
@@ -546,9 +1189,6 @@ impl Processor {
         )
         .as_str());
 
-        let clash_mint_data = Mint::unpack_unchecked(&clash_token_account.data.borrow())?;
-        let clash_decimals = clash_mint_data.decimals;
-
         // Transfer CLASH tokens from program ATA to account transferring SOL's
         let transfer_token_instruction = spl_token::instruction::transfer_checked(
             token_program_account.key, // token_program_id: &Pubkey
@@ -561,7 +1201,7 @@ impl Processor {
             clash_decimals,            // decimals: u8
         )?;
 
-        invoke_signed(
+        Self::invoke_sale_signed(
             &transfer_token_instruction,
             &[
                 program_token_account.clone(),
@@ -570,7 +1210,7 @@ impl Processor {
                 token_program_account.clone(),
                 program_pda_account.clone(),
             ],
-            &[&program_signature[..]],
+            &program_signature[..],
         )?;
 
         msg!(format!(
@@ -582,7 +1222,11 @@ impl Processor {
         Ok(())
     }
 
-    pub fn terminate_ico(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    pub fn terminate_ico(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+    ) -> ProgramResult {
         msg!("Terminating Clash ICO accounts and metadata.");
 
         // Get accounts
@@ -598,6 +1242,9 @@ impl Processor {
 
         let token_program_account = next_account_info(accounts_iter)?;
 
+        let vault_account = next_account_info(accounts_iter)?;
+        let clash_sol_wallet_account = next_account_info(accounts_iter)?;
+
         validate_account(initializer_account, true, false, true)?;
         validate_account(initializer_token_account, false, false, true)?;
 
@@ -606,14 +1253,19 @@ impl Processor {
         validate_account(program_pda_account, false, true, false)?;
         validate_account(program_token_account, false, true, false)?;
 
-        if clash_token_account.key != &CLASH_TOKEN_ID {
-            ico_err(ICOError::InvalidClashTokenId)?;
-        }
+        validate_account(vault_account, false, true, false)?;
+        validate_account(clash_sol_wallet_account, false, true, false)?;
 
-        let (program_pda, bump_seed) =
-            Pubkey::find_program_address(&[PROGRAM_PDA_SEED1, PROGRAM_PDA_SEED2], program_id);
+        let (program_pda, bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
 
-        let program_signature = &[&PROGRAM_PDA_SEED1, &PROGRAM_PDA_SEED2, &[bump_seed][..]];
+        let sale_id_seed = sale_id.to_le_bytes();
+        let program_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            &sale_id_seed[..],
+            clash_token_account.key.as_ref(),
+            &[bump_seed][..],
+        ];
 
         if program_pda_account.key != &program_pda {
             ico_err(ICOError::InvalidAddressProgramPDA)?;
@@ -623,7 +1275,7 @@ impl Processor {
             ico_err(ICOError::InvalidTerminateUninitializedICO)?;
         }
 
-        let ico_data = ICOProgramData::try_from_slice(&program_pda_account.data.borrow())?;
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
         msg!(format!(
             "Terminating an ICO initialized by `{}`",
             ico_data.initializer
@@ -634,10 +1286,90 @@ impl Processor {
             ico_err(ICOError::InitializerAccountMismatch)?;
         }
 
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
         if &ico_data.initializer_ata != initializer_token_account.key {
             ico_err(ICOError::InitializerAssociatedAccountMismatch)?;
         }
 
+        // A sale terminated before its end is cancelled, an ended sale is finalized unless
+        // it has a soft cap, which requires the finalize instruction to check it
+        ico_data.sync_status(Clock::get()?.unix_timestamp)?;
+
+        let cancelling = matches!(
+            ico_data.status,
+            SaleStatus::Pending | SaleStatus::Active | SaleStatus::Paused
+        );
+
+        match ico_data.status {
+            SaleStatus::Pending | SaleStatus::Active | SaleStatus::Paused => {
+                ico_data.set_status(SaleStatus::Cancelled)?
+            }
+            SaleStatus::Ended if ico_data.config.soft_cap_usd == 0 => {
+                ico_data.set_status(SaleStatus::Finalized)?
+            }
+            _ => ico_data.require_status(&[
+                SaleStatus::Finalized,
+                SaleStatus::Refunding,
+                SaleStatus::Cancelled,
+            ])?,
+        }
+
+        // Lamports and tokens owed to buyers must leave the sale before it can be closed. A
+        // sale cancelled with lamports escrowed stays open for its buyers to be refunded
+        if ico_data.escrowed_lamports > 0 || ico_data.unsettled_lamports > 0 {
+            // Buyers of a Dutch auction already received their tokens, their escrow is only
+            // released by finalizing the auction and claiming rebates
+            if cancelling && ico_data.has_auction_rebate() && !ico_data.refunds_on_cancel() {
+                ico_err(ICOError::RebateAuctionNotCancellable)?;
+            }
+
+            if !cancelling || !ico_data.refunds_on_cancel() {
+                ico_err(ICOError::SaleHasEscrowedFunds)?;
+            }
+
+            ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+            msg!("Sale cancelled, it is closed once its buyers are refunded.");
+
+            return Ok(());
+        }
+
+        // Tokens held by a soft cap sale are only owed once it is finalized, other claimable
+        // and vesting tokens are owed to buyers whatever the sale status
+        let owes_claimable = match ico_data.config.soft_cap_usd {
+            0 => ico_data.claimable_clash > 0,
+            _ => ico_data.status == SaleStatus::Finalized && ico_data.claimable_clash > 0,
+        };
+
+        if owes_claimable || ico_data.vesting_clash > 0 {
+            ico_err(ICOError::SaleHasOutstandingClaims)?;
+        }
+
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        // Every buyer is settled, the vault only holds its rent exemption and the rounding
+        // left by rebates, which go to the team wallet
+        let (vault_pda, _bump_seed) = find_vault_pda(program_id, &program_pda);
+
+        if vault_account.key != &vault_pda {
+            ico_err(ICOError::InvalidAddressVaultPDA)?;
+        }
+
+        if clash_sol_wallet_account.key != &ico_data.config.clash_sol_wallet {
+            ico_err(ICOError::InvalidClashTokenDestinationWallet)?;
+        }
+
+        if vault_account.owner == program_id && vault_account.lamports() > 0 {
+            Self::release_from_vault(
+                vault_account,
+                clash_sol_wallet_account,
+                vault_account.lamports(),
+            )?;
+        }
+
         let clash_mint_data = Mint::unpack_unchecked(&clash_token_account.data.borrow())?;
 
         if program_token_account.lamports() != 0 {
@@ -653,7 +1385,7 @@ impl Processor {
             validate_token_account(
                 &program_associated_token_account,
                 program_pda_account.key,
-                &CLASH_TOKEN_ID,
+                &ico_data.mint,
             )?;
 
             let amount_clash: u64 = program_associated_token_account.amount;
@@ -677,7 +1409,7 @@ impl Processor {
                     decimals,                      // decimals: u8
                 )?;
 
-                invoke_signed(
+                Self::invoke_sale_signed(
                     &transfer_token_instruction,
                     &[
                         program_token_account.clone(),
@@ -686,7 +1418,7 @@ impl Processor {
                         token_program_account.clone(),
                         program_pda_account.clone(),
                     ],
-                    &[&program_signature[..]],
+                    &program_signature[..],
                 )?;
             }
 
@@ -698,7 +1430,7 @@ impl Processor {
                 &[],                       // signer_pubkeys: &[&Pubkey]
             )?;
 
-            invoke_signed(
+            Self::invoke_sale_signed(
                 &close_instruction,
                 &[
                     program_token_account.clone(),
@@ -706,7 +1438,7 @@ impl Processor {
                     program_pda_account.clone(),
                     token_program_account.clone(),
                 ],
-                &[&program_signature[..]],
+                &program_signature[..],
             )?;
 
             msg!("Success closing Clash associated token account owned by the ICO program.");
@@ -733,4 +1465,1545 @@ impl Processor {
 
         Ok(())
     }
+
+    pub fn update_sale_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        data: &ICOSaleConfig,
+    ) -> ProgramResult {
+        msg!("Updating Clash ICO sale parameters.");
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let initializer_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+
+        validate_account(initializer_account, true, false, true)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+
+        let (program_pda, _bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if &ico_data.initializer != initializer_account.key {
+            ico_err(ICOError::InitializerAccountMismatch)?;
+        }
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        let status_changed = ico_data.sync_status(Clock::get()?.unix_timestamp)?;
+
+        ico_data.require_status(&[SaleStatus::Pending, SaleStatus::Active, SaleStatus::Paused])?;
+
+        validate_sale_config(data)?;
+        validate_sale_config_update(&ico_data, data)?;
+
+        let changed_fields = log_sale_config_changes(&ico_data.config, data);
+
+        if changed_fields == 0 && !status_changed {
+            msg!("Sale config is unchanged, nothing to update.");
+            return Ok(());
+        }
+
+        ico_data.config = data.clone();
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        msg!(format!(
+            "Sale config updated by `{}`: {} field(s) changed.",
+            initializer_account.key, changed_fields
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    pub fn pause_sale(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+    ) -> ProgramResult {
+        msg!("Pausing Clash ICO purchases and payments.");
+
+        Self::process_pause(program_id, accounts, sale_id, true)
+    }
+
+    pub fn resume_sale(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+    ) -> ProgramResult {
+        msg!("Resuming Clash ICO purchases and payments.");
+
+        Self::process_pause(program_id, accounts, sale_id, false)
+    }
+
+    // Shared by pause and resume instructions, the guardian can only pause a sale
+    fn process_pause(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        paused: bool,
+    ) -> ProgramResult {
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let authority_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+
+        validate_account(authority_account, true, false, true)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+
+        let (program_pda, _bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        ico_data.require_pause_authority(authority_account.key, paused)?;
+
+        let now = Clock::get()?.unix_timestamp;
+
+        ico_data.sync_status(now)?;
+
+        if paused {
+            ico_data.set_status(SaleStatus::Paused)?;
+        } else {
+            ico_data.require_status(&[SaleStatus::Paused])?;
+
+            // A sale paused before its start resumes as pending
+            if now < ico_data.config.start_timestamp {
+                ico_data.set_status(SaleStatus::Pending)?;
+            } else {
+                ico_data.set_status(SaleStatus::Active)?;
+            }
+        }
+
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        msg!(format!(
+            "Sale {} by `{}`.",
+            if paused { "paused" } else { "resumed" },
+            authority_account.key
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    pub fn commit_fair_launch(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        data: &FairLaunchCommitData,
+    ) -> ProgramResult {
+        msg!(format!("Committing {} lamports to a fair launch", data.lamports).as_str());
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let buyer_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+        let program_token_account = next_account_info(accounts_iter)?;
+        let vault_account = next_account_info(accounts_iter)?;
+
+        let buyer_record_account = next_account_info(accounts_iter)?;
+
+        let system_program_account = next_account_info(accounts_iter)?;
+
+        validate_account(buyer_account, true, true, true)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(program_token_account, false, false, true)?;
+        validate_account(vault_account, false, true, false)?;
+
+        validate_account(buyer_record_account, false, true, false)?;
+
+        let (program_pda, _bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        let clock = Clock::get()?;
+        Self::process_sale_window(program_pda_account, &mut ico_data, &clock)?;
+
+        let fair_launch = match ico_data.config.fair_launch {
+            Some(fair_launch) => fair_launch,
+            None => return ico_err(ICOError::NotFairLaunch),
+        };
+
+        if data.lamports == 0 {
+            ico_err(ICOError::InvalidCommitmentAmount)?;
+        }
+
+        if buyer_account.lamports() <= data.lamports {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        // Commitments are only accepted while the sale holds the tokens they are settled with
+        Self::require_sale_supply(
+            program_token_account,
+            &program_pda,
+            &ico_data.mint,
+            fair_launch.allocation,
+        )?;
+
+        let (mut buyer_record, new_buyer) = Self::load_buyer_record(
+            program_id,
+            buyer_record_account,
+            buyer_account,
+            system_program_account,
+            &program_pda,
+            buyer_account.key,
+        )?;
+
+        buyer_record.add_commitment(data.lamports, fair_launch.max_commit_lamports)?;
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+
+        Self::load_vault(
+            program_id,
+            vault_account,
+            buyer_account,
+            system_program_account,
+            &program_pda,
+        )?;
+
+        invoke(
+            &transfer(buyer_account.key, vault_account.key, data.lamports),
+            &[
+                buyer_account.clone(),
+                vault_account.clone(),
+                system_program_account.clone(),
+            ],
+        )?;
+
+        ico_data.add_commitment(data.lamports, new_buyer)?;
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    pub fn place_bid(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        data: &BidCommitData,
+    ) -> ProgramResult {
+        msg!(format!("Placing a sealed bid escrowing {} lamports", data.lamports).as_str());
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let bidder_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+        let program_token_account = next_account_info(accounts_iter)?;
+        let vault_account = next_account_info(accounts_iter)?;
+
+        let buyer_record_account = next_account_info(accounts_iter)?;
+
+        let system_program_account = next_account_info(accounts_iter)?;
+
+        validate_account(bidder_account, true, true, true)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(program_token_account, false, false, true)?;
+        validate_account(vault_account, false, true, false)?;
+
+        validate_account(buyer_record_account, false, true, false)?;
+
+        let (program_pda, _bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        let clock = Clock::get()?;
+        Self::process_sale_window(program_pda_account, &mut ico_data, &clock)?;
+
+        let batch_auction = match ico_data.config.batch_auction {
+            Some(batch_auction) => batch_auction,
+            None => return ico_err(ICOError::NotBatchAuction),
+        };
+
+        if data.lamports == 0 {
+            ico_err(ICOError::InvalidCommitmentAmount)?;
+        }
+
+        if bidder_account.lamports() <= data.lamports {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        // Bids are only accepted while the sale holds the tokens they are settled with
+        Self::require_sale_supply(
+            program_token_account,
+            &program_pda,
+            &ico_data.mint,
+            batch_auction.supply,
+        )?;
+
+        let (mut buyer_record, new_buyer) = Self::load_buyer_record(
+            program_id,
+            buyer_record_account,
+            bidder_account,
+            system_program_account,
+            &program_pda,
+            bidder_account.key,
+        )?;
+
+        if buyer_record.bid.is_some() {
+            ico_err(ICOError::BidAlreadyPlaced)?;
+        }
+
+        buyer_record.bid = Some(SealedBid {
+            commitment: data.commitment,
+            price_lamports: 0,
+            quantity: 0,
+            revealed: false,
+        });
+        buyer_record.add_escrow(data.lamports, 0, 0)?;
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+
+        Self::load_vault(
+            program_id,
+            vault_account,
+            bidder_account,
+            system_program_account,
+            &program_pda,
+        )?;
+
+        invoke(
+            &transfer(bidder_account.key, vault_account.key, data.lamports),
+            &[
+                bidder_account.clone(),
+                vault_account.clone(),
+                system_program_account.clone(),
+            ],
+        )?;
+
+        if new_buyer {
+            ico_data.buyer_count = ico_data
+                .buyer_count
+                .checked_add(1)
+                .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+        }
+
+        ico_data.add_escrow(data.lamports, 0, 0)?;
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    pub fn reveal_bid(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+        data: &BidRevealData,
+    ) -> ProgramResult {
+        msg!(format!(
+            "Revealing a bid of {} CLASH base units at {} lamports per CLASH",
+            data.quantity, data.price_lamports
+        )
+        .as_str());
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let bidder_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+
+        let buyer_record_account = next_account_info(accounts_iter)?;
+
+        validate_account(bidder_account, true, false, true)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+
+        validate_account(buyer_record_account, false, true, true)?;
+
+        let (program_pda, _bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        let batch_auction = match ico_data.config.batch_auction {
+            Some(batch_auction) => batch_auction,
+            None => return ico_err(ICOError::NotBatchAuction),
+        };
+
+        // Bids are revealed between the end of the sale and the end of the reveal window
+        let now = Clock::get()?.unix_timestamp;
+
+        ico_data.sync_status(now)?;
+        ico_data.require_status(&[SaleStatus::Ended])?;
+
+        if now >= batch_auction.reveal_end_timestamp {
+            ico_err(ICOError::BidRevealClosed)?;
+        }
+
+        let mut buyer_record = Self::unpack_buyer_record(
+            program_id,
+            buyer_record_account,
+            &program_pda,
+            bidder_account.key,
+        )?;
+
+        let bid = match &mut buyer_record.bid {
+            Some(bid) => bid,
+            None => return ico_err(ICOError::NoBid),
+        };
+
+        if bid.revealed {
+            ico_err(ICOError::BidAlreadyRevealed)?;
+        }
+
+        let commitment = bid_commitment(
+            bidder_account.key,
+            data.price_lamports,
+            data.quantity,
+            &data.salt,
+        );
+
+        if commitment != bid.commitment {
+            ico_err(ICOError::BidRevealMismatch)?;
+        }
+
+        let tick = batch_auction
+            .price_tick(data.price_lamports)
+            .ok_or_else(|| ico_error(ICOError::InvalidBidPrice))?;
+
+        if data.quantity == 0 {
+            ico_err(ICOError::InvalidClashTokenAmount)?;
+        }
+
+        let clash_mint_data = Mint::unpack_unchecked(&clash_token_account.data.borrow())?;
+        let cost = SealedBid::cost(data.quantity, data.price_lamports, clash_mint_data.decimals)?;
+
+        if cost > buyer_record.escrowed_lamports {
+            msg!(format!(
+                "Bid costs {} lamports, {} lamports are escrowed",
+                cost, buyer_record.escrowed_lamports
+            )
+            .as_str());
+
+            ico_err(ICOError::InsufficientBidEscrow)?;
+        }
+
+        bid.price_lamports = data.price_lamports;
+        bid.quantity = data.quantity;
+        bid.revealed = true;
+
+        ico_data.bid_demand[tick] = ico_data.bid_demand[tick]
+            .checked_add(data.quantity)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    pub fn settle_bid(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+    ) -> ProgramResult {
+        msg!("Settling a batch auction bid at the clearing price.");
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let bidder_account = next_account_info(accounts_iter)?;
+        let bidder_token_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+        let program_token_account = next_account_info(accounts_iter)?;
+        let vault_account = next_account_info(accounts_iter)?;
+
+        let clash_sol_wallet_account = next_account_info(accounts_iter)?;
+
+        let buyer_record_account = next_account_info(accounts_iter)?;
+
+        let system_program_account = next_account_info(accounts_iter)?;
+        let token_program_account = next_account_info(accounts_iter)?;
+        let associated_token_account_program = next_account_info(accounts_iter)?;
+        let sysvar_rent_program_account = next_account_info(accounts_iter)?;
+
+        // Bids can be settled on behalf of the bidder, the payer funds its token account while
+        // tokens and refunds always go to the bidder
+        let payer_account = next_account_info(accounts_iter)?;
+
+        validate_account(bidder_account, false, true, true)?;
+        validate_account(bidder_token_account, false, true, false)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(program_token_account, false, true, true)?;
+        validate_account(vault_account, false, true, true)?;
+
+        validate_account(clash_sol_wallet_account, false, true, false)?;
+
+        validate_account(buyer_record_account, false, true, true)?;
+
+        validate_account(payer_account, true, true, true)?;
+
+        let (program_pda, bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        let sale_id_seed = sale_id.to_le_bytes();
+        let program_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            &sale_id_seed[..],
+            clash_token_account.key.as_ref(),
+            &[bump_seed][..],
+        ];
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        if ico_data.config.batch_auction.is_none() {
+            ico_err(ICOError::NotBatchAuction)?;
+        }
+
+        ico_data.require_status(&[SaleStatus::Finalized])?;
+
+        let (vault_pda, _bump_seed) = find_vault_pda(program_id, &program_pda);
+
+        if vault_account.key != &vault_pda {
+            ico_err(ICOError::InvalidAddressVaultPDA)?;
+        }
+
+        if clash_sol_wallet_account.key != &ico_data.config.clash_sol_wallet {
+            ico_err(ICOError::InvalidClashTokenDestinationWallet)?;
+        }
+
+        let mut buyer_record = Self::unpack_buyer_record(
+            program_id,
+            buyer_record_account,
+            &program_pda,
+            bidder_account.key,
+        )?;
+
+        let bid = match buyer_record.bid.take() {
+            Some(bid) => bid,
+            None => return ico_err(ICOError::NoBid),
+        };
+
+        let clash_mint_data = Mint::unpack_unchecked(&clash_token_account.data.borrow())?;
+        let clash_decimals = clash_mint_data.decimals;
+
+        // Winners pay the clearing price for the tokens they won, and every bidder gets back
+        // the rest of its escrow
+        let escrowed_lamports = buyer_record.escrowed_lamports;
+        let clash_amount_final = ico_data.bid_fill(&bid)?;
+        let paid_lamports = SealedBid::cost(
+            clash_amount_final,
+            ico_data.clearing_lamports,
+            clash_decimals,
+        )?
+        .min(escrowed_lamports);
+        let refund_lamports = escrowed_lamports - paid_lamports;
+
+        msg!(format!(
+            "Bid wins {} CLASH for {} lamports, {} lamports are refunded",
+            format_amount(clash_amount_final, clash_decimals),
+            paid_lamports,
+            refund_lamports
+        )
+        .as_str());
+
+        if clash_amount_final > 0 {
+            validate_token_account(
+                &TokenAccount::unpack(&program_token_account.data.borrow())?,
+                &program_pda,
+                clash_token_account.key,
+            )?;
+
+            Self::load_token_account(
+                bidder_token_account,
+                bidder_account,
+                payer_account,
+                clash_token_account,
+                system_program_account,
+                token_program_account,
+                associated_token_account_program,
+                sysvar_rent_program_account,
+            )?;
+
+            let transfer_token_instruction = spl_token::instruction::transfer_checked(
+                token_program_account.key, // token_program_id: &Pubkey
+                program_token_account.key, // source_pubkey: &Pubkey
+                clash_token_account.key,   // mint_pubkey: &Pubkey
+                bidder_token_account.key,  // destination_pubkey: &Pubkey
+                program_pda_account.key,   // authority_pubkey: &Pubkey
+                &[],                       // signer_pubkeys: &[&Pubkey]
+                clash_amount_final,        // amount: u64
+                clash_decimals,            // decimals: u8
+            )?;
+
+            Self::invoke_sale_signed(
+                &transfer_token_instruction,
+                &[
+                    program_token_account.clone(),
+                    bidder_token_account.clone(),
+                    clash_token_account.clone(),
+                    token_program_account.clone(),
+                    program_pda_account.clone(),
+                ],
+                &program_signature[..],
+            )?;
+        }
+
+        if paid_lamports > 0 {
+            Self::release_from_vault(vault_account, clash_sol_wallet_account, paid_lamports)?;
+        }
+
+        if refund_lamports > 0 {
+            Self::release_from_vault(vault_account, bidder_account, refund_lamports)?;
+        }
+
+        buyer_record.escrowed_lamports = 0;
+        buyer_record.lamports_paid = buyer_record
+            .lamports_paid
+            .checked_add(paid_lamports)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+        buyer_record.clash_received = buyer_record
+            .clash_received
+            .checked_add(clash_amount_final)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+        ico_data.escrowed_lamports = ico_data.escrowed_lamports.saturating_sub(escrowed_lamports);
+        ico_data.lamports_raised = ico_data
+            .lamports_raised
+            .checked_add(paid_lamports)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+        ico_data.clash_sold = ico_data
+            .clash_sold
+            .checked_add(clash_amount_final)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    pub fn finalize_sale(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+    ) -> ProgramResult {
+        msg!("Finalizing Clash ICO after its end.");
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let caller_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+        let vault_account = next_account_info(accounts_iter)?;
+
+        let clash_sol_wallet_account = next_account_info(accounts_iter)?;
+
+        validate_account(caller_account, true, false, true)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(vault_account, false, true, false)?;
+
+        validate_account(clash_sol_wallet_account, false, true, false)?;
+
+        let (program_pda, _bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+
+        ico_data.sync_status(now)?;
+        ico_data.require_status(&[SaleStatus::Ended])?;
+
+        if let Some(batch_auction) = &ico_data.config.batch_auction {
+            if now < batch_auction.reveal_end_timestamp {
+                ico_err(ICOError::BidRevealOpen)?;
+            }
+        }
+
+        msg!(format!(
+            "Sale raised {}USD, soft cap is {}USD",
+            format_amount(ico_data.raised_usd, USD_DECIMALS),
+            format_amount(ico_data.config.soft_cap_usd, USD_DECIMALS)
+        )
+        .as_str());
+
+        let released_lamports = ico_data.finalize()?;
+
+        if released_lamports > 0 {
+            let (vault_pda, _bump_seed) = find_vault_pda(program_id, &program_pda);
+
+            if vault_account.key != &vault_pda {
+                ico_err(ICOError::InvalidAddressVaultPDA)?;
+            }
+
+            if clash_sol_wallet_account.key != &ico_data.config.clash_sol_wallet {
+                ico_err(ICOError::InvalidClashTokenDestinationWallet)?;
+            }
+
+            Self::release_from_vault(vault_account, clash_sol_wallet_account, released_lamports)?;
+        }
+
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    pub fn claim_rebate(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+    ) -> ProgramResult {
+        msg!("Claiming the lamports paid above the Dutch auction clearing price.");
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let buyer_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+        let vault_account = next_account_info(accounts_iter)?;
+
+        let buyer_record_account = next_account_info(accounts_iter)?;
+
+        // Rebates can be cranked on behalf of the buyer, they are always paid to its wallet
+        validate_account(buyer_account, false, true, true)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(vault_account, false, true, true)?;
+
+        validate_account(buyer_record_account, false, true, true)?;
+
+        let (program_pda, _bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        if !ico_data.has_auction_rebate() {
+            ico_err(ICOError::NoAuctionRebate)?;
+        }
+
+        ico_data.require_status(&[SaleStatus::Finalized])?;
+
+        let (vault_pda, _bump_seed) = find_vault_pda(program_id, &program_pda);
+
+        if vault_account.key != &vault_pda {
+            ico_err(ICOError::InvalidAddressVaultPDA)?;
+        }
+
+        let mut buyer_record = Self::unpack_buyer_record(
+            program_id,
+            buyer_record_account,
+            &program_pda,
+            buyer_account.key,
+        )?;
+
+        if buyer_record.escrowed_lamports == 0 {
+            ico_err(ICOError::NothingToRefund)?;
+        }
+
+        let rebate_lamports = ico_data.release_rebate(&mut buyer_record)?;
+
+        if rebate_lamports > 0 {
+            Self::release_from_vault(vault_account, buyer_account, rebate_lamports)?;
+        }
+
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    pub fn refund_purchase(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+    ) -> ProgramResult {
+        msg!("Refunding escrowed lamports of a cancelled sale, of a sale that missed its soft cap or of a fair launch.");
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let buyer_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+        let vault_account = next_account_info(accounts_iter)?;
+
+        let buyer_record_account = next_account_info(accounts_iter)?;
+
+        // Refunds can be cranked on behalf of the buyer, they are always paid to its wallet
+        validate_account(buyer_account, false, true, true)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(vault_account, false, true, true)?;
+
+        validate_account(buyer_record_account, false, true, true)?;
+
+        let (program_pda, _bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        // Buyers of a cancelled sale get back everything they escrowed, fair launch buyers
+        // otherwise get back the part of their commitment that was not filled
+        if ico_data.config.fair_launch.is_some() {
+            ico_data.require_status(&[SaleStatus::Finalized, SaleStatus::Cancelled])?;
+        } else {
+            ico_data.require_status(&[SaleStatus::Refunding, SaleStatus::Cancelled])?;
+        }
+
+        let cancelled = ico_data.status == SaleStatus::Cancelled;
+
+        let (vault_pda, _bump_seed) = find_vault_pda(program_id, &program_pda);
+
+        if vault_account.key != &vault_pda {
+            ico_err(ICOError::InvalidAddressVaultPDA)?;
+        }
+
+        let mut buyer_record = Self::unpack_buyer_record(
+            program_id,
+            buyer_record_account,
+            &program_pda,
+            buyer_account.key,
+        )?;
+
+        let settling = !cancelled && buyer_record.committed_lamports > 0;
+
+        if cancelled {
+            ico_data.cancel_commitment(&mut buyer_record)?;
+        } else {
+            ico_data.settle_commitment(&mut buyer_record)?;
+        }
+
+        let lamports_amount = buyer_record.escrowed_lamports;
+
+        if lamports_amount == 0 && settling {
+            // Settling a fully filled commitment leaves nothing to refund but tokens to claim
+            buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+            ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+            return Ok(());
+        }
+
+        if lamports_amount == 0 {
+            ico_err(ICOError::NothingToRefund)?;
+        }
+
+        Self::release_from_vault(vault_account, buyer_account, lamports_amount)?;
+
+        ico_data.refund_escrow(&mut buyer_record);
+
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    pub fn claim_tokens(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        sale_id: u64,
+    ) -> ProgramResult {
+        msg!("Claiming Clash tokens bought in a finalized sale, vested or distributed at the TGE.");
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let buyer_account = next_account_info(accounts_iter)?;
+        let buyer_token_account = next_account_info(accounts_iter)?;
+
+        let clash_token_account = next_account_info(accounts_iter)?;
+
+        let program_pda_account = next_account_info(accounts_iter)?;
+        let program_token_account = next_account_info(accounts_iter)?;
+
+        let buyer_record_account = next_account_info(accounts_iter)?;
+
+        let system_program_account = next_account_info(accounts_iter)?;
+        let token_program_account = next_account_info(accounts_iter)?;
+        let associated_token_account_program = next_account_info(accounts_iter)?;
+        let sysvar_rent_program_account = next_account_info(accounts_iter)?;
+
+        // Claims can be cranked on behalf of the buyer, the payer funds its token account
+        let payer_account = next_account_info(accounts_iter)?;
+
+        validate_account(buyer_account, false, false, true)?;
+        validate_account(buyer_token_account, false, true, false)?;
+
+        validate_account(clash_token_account, false, false, true)?;
+
+        validate_account(program_pda_account, false, true, true)?;
+        validate_account(program_token_account, false, true, true)?;
+
+        validate_account(buyer_record_account, false, true, true)?;
+
+        validate_account(payer_account, true, true, true)?;
+
+        let (program_pda, bump_seed) = find_sale_pda(program_id, sale_id, clash_token_account.key);
+
+        let sale_id_seed = sale_id.to_le_bytes();
+        let program_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            &sale_id_seed[..],
+            clash_token_account.key.as_ref(),
+            &[bump_seed][..],
+        ];
+
+        if program_pda_account.key != &program_pda {
+            ico_err(ICOError::InvalidAddressProgramPDA)?;
+        }
+
+        if program_pda_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedICO)?;
+        }
+
+        let mut ico_data = ICOProgramData::unpack(&program_pda_account.data.borrow())?;
+
+        if clash_token_account.key != &ico_data.mint {
+            ico_err(ICOError::InvalidClashTokenId)?;
+        }
+
+        // Soft cap sales and fair launches owe tokens once finalized, vesting and TGE tokens
+        // are released whatever the sale status
+        if ico_data.config.soft_cap_usd > 0 || ico_data.config.fair_launch.is_some() {
+            ico_data.require_status(&[SaleStatus::Finalized])?;
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+
+        validate_token_account(
+            &TokenAccount::unpack(&program_token_account.data.borrow())?,
+            &program_pda,
+            clash_token_account.key,
+        )?;
+
+        let mut buyer_record = Self::unpack_buyer_record(
+            program_id,
+            buyer_record_account,
+            &program_pda,
+            buyer_account.key,
+        )?;
+
+        // Fair launch commitments are settled on the first claim or refund
+        ico_data.settle_commitment(&mut buyer_record)?;
+
+        let clash_amount_final = ico_data.release_claim(&mut buyer_record, now)?;
+
+        if clash_amount_final == 0 {
+            ico_err(ICOError::NothingToClaim)?;
+        }
+
+        Self::load_token_account(
+            buyer_token_account,
+            buyer_account,
+            payer_account,
+            clash_token_account,
+            system_program_account,
+            token_program_account,
+            associated_token_account_program,
+            sysvar_rent_program_account,
+        )?;
+
+        let clash_mint_data = Mint::unpack_unchecked(&clash_token_account.data.borrow())?;
+        let clash_decimals = clash_mint_data.decimals;
+
+        let transfer_token_instruction = spl_token::instruction::transfer_checked(
+            token_program_account.key, // token_program_id: &Pubkey
+            program_token_account.key, // source_pubkey: &Pubkey
+            clash_token_account.key,   // mint_pubkey: &Pubkey
+            buyer_token_account.key,   // destination_pubkey: &Pubkey
+            program_pda_account.key,   // authority_pubkey: &Pubkey
+            &[],                       // signer_pubkeys: &[&Pubkey]
+            clash_amount_final,        // amount: u64
+            clash_decimals,            // decimals: u8
+        )?;
+
+        Self::invoke_sale_signed(
+            &transfer_token_instruction,
+            &[
+                program_token_account.clone(),
+                buyer_token_account.clone(),
+                clash_token_account.clone(),
+                token_program_account.clone(),
+                program_pda_account.clone(),
+            ],
+            &program_signature[..],
+        )?;
+
+        buyer_record.serialize(&mut &mut buyer_record_account.data.borrow_mut()[..])?;
+        ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+
+        msg!(format!(
+            "Success claiming {} CLASH tokens to `{}`.",
+            format_amount(clash_amount_final, clash_decimals),
+            buyer_token_account.key
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    pub fn update_price(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        data: &PriceFeedUpdateData,
+    ) -> ProgramResult {
+        msg!("Publishing SOL/USD quote to the program price feed.");
+
+        // Get accounts
+        let accounts_iter = &mut accounts.iter();
+
+        let publisher_account = next_account_info(accounts_iter)?;
+        let price_feed_account = next_account_info(accounts_iter)?;
+
+        let system_program_account = next_account_info(accounts_iter)?;
+
+        validate_account(publisher_account, true, true, true)?;
+        validate_account(price_feed_account, false, true, false)?;
+
+        if data.price <= 0 {
+            ico_err(ICOError::InvalidOraclePrice)?;
+        }
+
+        if data.expo.unsigned_abs() > u8::MAX as u32 {
+            ico_err(ICOError::InvalidPriceFeedExponent)?;
+        }
+
+        let (price_feed_pda, bump_seed) = find_price_feed_pda(program_id, publisher_account.key);
+
+        let price_feed_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            PRICE_FEED_SEED,
+            publisher_account.key.as_ref(),
+            &[bump_seed][..],
+        ];
+
+        if price_feed_account.key != &price_feed_pda {
+            ico_err(ICOError::InvalidAddressPriceFeedPDA)?;
+        }
+
+        if price_feed_account.owner != program_id {
+            msg!(format!(
+                "Creating price feed account(PDA) `{}` for publisher `{}`",
+                price_feed_account.key, publisher_account.key
+            )
+            .as_str());
+
+            Self::create_pda_account(
+                program_id,
+                price_feed_account,
+                publisher_account,
+                system_program_account,
+                PriceFeed::LEN,
+                price_feed_signature,
+            )?;
+        }
+
+        let clock = Clock::get()?;
+
+        let price_feed = PriceFeed {
+            publisher: *publisher_account.key,
+            price: data.price,
+            expo: data.expo,
+            publish_slot: clock.slot,
+            publish_timestamp: clock.unix_timestamp,
+        };
+
+        price_feed.serialize(&mut &mut price_feed_account.data.borrow_mut()[..])?;
+
+        msg!(format!(
+            "Price feed `{}` updated: {} x 10^{} USD at slot {}",
+            price_feed_account.key, price_feed.price, price_feed.expo, price_feed.publish_slot
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    // Rejects purchases outside of the sale window or while the sale is not active, and
+    // extends the window when a purchase lands at its end
+    fn process_sale_window(
+        program_pda_account: &AccountInfo,
+        ico_data: &mut ICOProgramData,
+        clock: &Clock,
+    ) -> ProgramResult {
+        validate_sale_window(&ico_data.config, clock.unix_timestamp)?;
+
+        let mut changed = ico_data.sync_status(clock.unix_timestamp)?;
+
+        if ico_data.status == SaleStatus::Paused {
+            ico_err(ICOError::SalePaused)?;
+        }
+
+        if ico_data.hard_cap_reached() {
+            ico_err(ICOError::HardCapReached)?;
+        }
+
+        ico_data.require_status(&[SaleStatus::Active])?;
+
+        if let Some(end_timestamp) = ico_data.config.extend_sale_window(clock.unix_timestamp) {
+            msg!(format!("Sale end extended to {}", end_timestamp).as_str());
+
+            changed = true;
+        }
+
+        if changed {
+            ico_data.serialize(&mut &mut program_pda_account.data.borrow_mut()[..])?;
+        }
+
+        Ok(())
+    }
+
+    // Loads the purchases of a buyer in a sale, creating its record on the first purchase.
+    // Also returns whether the record was just created, so each buyer is counted once
+    fn load_buyer_record<'a>(
+        program_id: &Pubkey,
+        buyer_record_account: &AccountInfo<'a>,
+        funding_account: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        sale_pda: &Pubkey,
+        buyer: &Pubkey,
+    ) -> Result<(BuyerRecord, bool), ProgramError> {
+        let (buyer_record_pda, bump_seed) = find_buyer_record_pda(program_id, sale_pda, buyer);
+
+        if buyer_record_account.key != &buyer_record_pda {
+            ico_err(ICOError::InvalidAddressBuyerRecordPDA)?;
+        }
+
+        if buyer_record_account.owner == program_id {
+            let buyer_record =
+                Self::unpack_buyer_record(program_id, buyer_record_account, sale_pda, buyer)?;
+
+            return Ok((buyer_record, false));
+        }
+
+        msg!(format!(
+            "Creating buyer record account(PDA) `{}` for buyer `{}`",
+            buyer_record_account.key, buyer
+        )
+        .as_str());
+
+        let buyer_record_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            BUYER_RECORD_SEED,
+            sale_pda.as_ref(),
+            buyer.as_ref(),
+            &[bump_seed][..],
+        ];
+
+        Self::create_pda_account(
+            program_id,
+            buyer_record_account,
+            funding_account,
+            system_program_account,
+            BuyerRecord::LEN,
+            buyer_record_signature,
+        )?;
+
+        let buyer_record = BuyerRecord {
+            sale: *sale_pda,
+            buyer: *buyer,
+            lamports_paid: 0,
+            usd_paid: 0,
+            clash_received: 0,
+            escrowed_lamports: 0,
+            claimable_clash: 0,
+            rebate_weight: 0,
+            committed_lamports: 0,
+            bid: None,
+            vesting_clash: 0,
+            released_clash: 0,
+        };
+
+        Ok((buyer_record, true))
+    }
+
+    // Loads the purchases of a buyer in a sale from an existing record
+    fn unpack_buyer_record(
+        program_id: &Pubkey,
+        buyer_record_account: &AccountInfo,
+        sale_pda: &Pubkey,
+        buyer: &Pubkey,
+    ) -> Result<BuyerRecord, ProgramError> {
+        let (buyer_record_pda, _bump_seed) = find_buyer_record_pda(program_id, sale_pda, buyer);
+
+        if buyer_record_account.key != &buyer_record_pda {
+            ico_err(ICOError::InvalidAddressBuyerRecordPDA)?;
+        }
+
+        if buyer_record_account.lamports() == 0 {
+            ico_err(ICOError::UninitializedBuyerRecord)?;
+        }
+
+        if buyer_record_account.owner != program_id {
+            ico_err(ICOError::InvalidBuyerRecordOwner)?;
+        }
+
+        BuyerRecord::unpack(&buyer_record_account.data.borrow())
+    }
+
+    // Creates the associated token account of `owner_account` for the sale mint when it does
+    // not exist yet, paid by `funding_account`, otherwise checks its owner and mint
+    #[allow(clippy::too_many_arguments)]
+    fn load_token_account<'a>(
+        token_account: &AccountInfo<'a>,
+        owner_account: &AccountInfo<'a>,
+        funding_account: &AccountInfo<'a>,
+        clash_token_account: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        token_program_account: &AccountInfo<'a>,
+        associated_token_account_program: &AccountInfo<'a>,
+        sysvar_rent_program_account: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        if token_account.lamports() != 0 {
+            return validate_token_account(
+                &TokenAccount::unpack(&token_account.data.borrow())?,
+                owner_account.key,
+                clash_token_account.key,
+            );
+        }
+
+        msg!(format!(
+            "Creating ATA account `{}` because it does not exists yet",
+            token_account.key
+        )
+        .as_str());
+
+        let create_ata_instruction = spl_associated_token_account::create_associated_token_account(
+            funding_account.key,
+            owner_account.key,
+            clash_token_account.key,
+        );
+
+        invoke(
+            &create_ata_instruction,
+            &[
+                funding_account.clone(),
+                token_account.clone(),
+                owner_account.clone(),
+                clash_token_account.clone(),
+                system_program_account.clone(),
+                token_program_account.clone(),
+                associated_token_account_program.clone(),
+                sysvar_rent_program_account.clone(),
+            ],
+        )?;
+
+        msg!(format!(
+            "Success creating ATA account `{}` for account `{}`",
+            token_account.key, owner_account.key
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    // Checks the escrow vault of a sale, creating it on the first escrowed purchase
+    fn load_vault<'a>(
+        program_id: &Pubkey,
+        vault_account: &AccountInfo<'a>,
+        funding_account: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        sale_pda: &Pubkey,
+    ) -> ProgramResult {
+        let (vault_pda, bump_seed) = find_vault_pda(program_id, sale_pda);
+
+        if vault_account.key != &vault_pda {
+            ico_err(ICOError::InvalidAddressVaultPDA)?;
+        }
+
+        // Escrowed lamports are debited directly, which requires the program to own the vault
+        if vault_account.owner == program_id {
+            return Ok(());
+        }
+
+        msg!(format!(
+            "Creating escrow vault account(PDA) `{}` for sale `{}`",
+            vault_account.key, sale_pda
+        )
+        .as_str());
+
+        let vault_signature = &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            VAULT_SEED,
+            sale_pda.as_ref(),
+            &[bump_seed][..],
+        ];
+
+        // Vault holds no data, whatever it holds once buyers are settled is released when the
+        // sale is terminated
+        Self::create_pda_account(
+            program_id,
+            vault_account,
+            funding_account,
+            system_program_account,
+            0,
+            vault_signature,
+        )
+    }
+
+    // Moves escrowed lamports out of a sale vault, which is owned by the program
+    fn release_from_vault(
+        vault_account: &AccountInfo,
+        destination_account: &AccountInfo,
+        lamports_amount: u64,
+    ) -> ProgramResult {
+        **vault_account.try_borrow_mut_lamports()? = vault_account
+            .lamports()
+            .checked_sub(lamports_amount)
+            .ok_or(ProgramError::InsufficientFunds)?;
+
+        **destination_account.try_borrow_mut_lamports()? = destination_account
+            .lamports()
+            .checked_add(lamports_amount)
+            .ok_or(ProgramError::InvalidArgument)?;
+
+        msg!(format!(
+            "Success transferred {} escrowed lamports from `{}` to `{}`.",
+            lamports_amount, vault_account.key, destination_account.key
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    // Checks the sale token account holds at least the tokens a sale mode sells in full
+    fn require_sale_supply(
+        program_token_account: &AccountInfo,
+        program_pda: &Pubkey,
+        mint: &Pubkey,
+        supply: u64,
+    ) -> ProgramResult {
+        let program_associated_token_account =
+            TokenAccount::unpack(&program_token_account.data.borrow())?;
+
+        validate_token_account(&program_associated_token_account, program_pda, mint)?;
+
+        if program_associated_token_account.amount < supply {
+            msg!(format!(
+                "Sale token account holds {} CLASH base units, {} are required",
+                program_associated_token_account.amount, supply
+            )
+            .as_str());
+
+            ico_err(ICOError::InsuficientClashToken)?;
+        }
+
+        Ok(())
+    }
+
+    // Invokes a token instruction signed by the sale PDA. The signature lets the instruction
+    // move the sale tokens, so only the SPL token program is trusted with it
+    fn invoke_sale_signed(
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        program_signature: &[&[u8]],
+    ) -> ProgramResult {
+        if instruction.program_id != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        invoke_signed(instruction, account_infos, &[program_signature])
+    }
+
+    // Creates a rent exempt PDA owned by the program. Anyone can send lamports to a PDA
+    // before it is created, which makes `create_account` fail, so a funded PDA is topped up
+    // then allocated and assigned instead
+    fn create_pda_account<'a>(
+        program_id: &Pubkey,
+        pda_account: &AccountInfo<'a>,
+        funding_account: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        data_size: usize,
+        signer_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let lamports_amount = Rent::get()?.minimum_balance(data_size);
+
+        let accounts = &[
+            funding_account.clone(),
+            pda_account.clone(),
+            system_program_account.clone(),
+        ];
+
+        if pda_account.lamports() == 0 {
+            let create_instruction = system_instruction::create_account(
+                funding_account.key,
+                pda_account.key,
+                lamports_amount,
+                data_size as u64,
+                program_id,
+            );
+
+            return invoke_signed(&create_instruction, accounts, &[signer_seeds]);
+        }
+
+        let top_up_amount = lamports_amount.saturating_sub(pda_account.lamports());
+
+        if top_up_amount > 0 {
+            invoke(
+                &transfer(funding_account.key, pda_account.key, top_up_amount),
+                accounts,
+            )?;
+        }
+
+        invoke_signed(
+            &system_instruction::allocate(pda_account.key, data_size as u64),
+            accounts,
+            &[signer_seeds],
+        )?;
+
+        invoke_signed(
+            &system_instruction::assign(pda_account.key, program_id),
+            accounts,
+            &[signer_seeds],
+        )
+    }
 }
@@ -0,0 +1,680 @@
+use solana_program::{msg, program_error::ProgramError};
+
+use crate::error::{ico_error, ICOError};
+
+use crate::pricing::{
+    mul_div, payment_to_tokens, to_u64, token_unit, tokens_to_payment, tokens_to_usd, Rounding,
+};
+
+use crate::state::ICOProgramData;
+
+// Scale applied to the lamports weight of Dutch auction purchases
+const REBATE_WEIGHT_SCALE: u128 = 1_000_000;
+
+// Scale applied to curve prices so the average price of a purchase keeps a sub micro USD
+// precision before being multiplied by the tokens bought
+const CURVE_PRICE_SCALE: u128 = 1_000_000;
+
+// Price over `length` CLASH base units of which `sold` are already sold, moving linearly
+// from `start_usd` to `end_usd` micro USD per token. Flat segments have the same price at
+// both ends
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PriceSegment {
+    pub start_usd: u64,
+    pub end_usd: u64,
+    pub length: u64,
+    pub sold: u64,
+}
+
+// Amounts exchanged by a purchase against the sale price schedule
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Fill {
+    pub clash: u64,
+    pub payment: u64,
+}
+
+// Prices of the tokens left to sell, in selling order. Fixed price sales have a single
+// unbounded segment, tiered sales a segment per open round from the current one and
+// bonding curve sales a segment per curve section left
+pub struct PriceSchedule {
+    segments: Vec<PriceSegment>,
+}
+
+impl PriceSchedule {
+    pub fn load(ico_data: &ICOProgramData, now: i64) -> Result<Self, ProgramError> {
+        let config = &ico_data.config;
+
+        if let Some(clash_usd) = ico_data.auction_price(now) {
+            return Ok(Self {
+                segments: vec![PriceSegment::flat(clash_usd, u64::MAX, 0)],
+            });
+        }
+
+        if !config.bonding_curve.is_empty() {
+            let segments = config
+                .bonding_curve
+                .windows(2)
+                .filter(|points| ico_data.clash_sold < points[1].clash_sold)
+                .map(|points| PriceSegment {
+                    start_usd: points[0].clash_usd,
+                    end_usd: points[1].clash_usd,
+                    length: points[1].clash_sold - points[0].clash_sold,
+                    sold: ico_data.clash_sold.saturating_sub(points[0].clash_sold),
+                })
+                .collect();
+
+            return Ok(Self { segments });
+        }
+
+        if config.rounds.is_empty() {
+            return Ok(Self {
+                segments: vec![PriceSegment::flat(config.clash_usd, u64::MAX, 0)],
+            });
+        }
+
+        let current_round = ico_data.current_round as usize;
+        let mut segments = Vec::new();
+
+        // A purchase spills over the following rounds as long as they are open
+        for (index, round) in config.rounds.iter().enumerate().skip(current_round) {
+            if round.has_ended(now) {
+                continue;
+            }
+
+            if !round.has_started(now) {
+                break;
+            }
+
+            let sold = if index == current_round {
+                ico_data.round_sold.min(round.allocation)
+            } else {
+                0
+            };
+
+            if sold < round.allocation {
+                segments.push(PriceSegment::flat(round.clash_usd, round.allocation, sold));
+            }
+        }
+
+        if segments.is_empty() {
+            msg!(format!(
+                "No price round open at {}, current round is {}",
+                now, current_round
+            )
+            .as_str());
+
+            return Err(ico_error(ICOError::NoOpenPriceRound));
+        }
+
+        Ok(Self { segments })
+    }
+
+    // Tokens bought by a payment through the segments in order, rounded down in favour of
+    // the sale. The fill pays less than `payment` when the schedule runs out of tokens
+    pub fn fill_exact_in(
+        &self,
+        payment: u64,
+        payment_usd: u64,
+        payment_decimals: u8,
+        decimals: u8,
+    ) -> Result<Fill, ProgramError> {
+        let mut fill = Fill::default();
+        let mut payment_left = payment;
+
+        for segment in &self.segments {
+            if payment_left == 0 {
+                break;
+            }
+
+            let available = segment.available();
+            let clash =
+                segment.tokens_for(payment_left, payment_usd, payment_decimals, decimals)?;
+
+            // What is left of the payment cannot buy one more token of the segment
+            let (clash, cost) = if clash < available {
+                (clash, payment_left)
+            } else {
+                let cost = segment.cost(available, payment_usd, payment_decimals, decimals)?;
+
+                (available, cost.min(payment_left))
+            };
+
+            fill.add(clash, cost)?;
+            payment_left -= cost;
+        }
+
+        Ok(fill)
+    }
+
+    // Payment owed for the next `clash` tokens, rounded up in favour of the sale. The fill
+    // holds fewer tokens than `clash` when the schedule runs out of tokens
+    pub fn fill_exact_out(
+        &self,
+        clash: u64,
+        payment_usd: u64,
+        payment_decimals: u8,
+        decimals: u8,
+    ) -> Result<Fill, ProgramError> {
+        let mut fill = Fill::default();
+        let mut clash_left = clash;
+
+        for segment in &self.segments {
+            if clash_left == 0 {
+                break;
+            }
+
+            let clash = clash_left.min(segment.available());
+            let cost = segment.cost(clash, payment_usd, payment_decimals, decimals)?;
+
+            fill.add(clash, cost)?;
+            clash_left -= clash;
+        }
+
+        Ok(fill)
+    }
+
+    // Payment owed for the `clash` tokens an exchange is partially filled with, along with its
+    // USD value rounded down like the value of any other payment
+    pub fn partial_fill(
+        &self,
+        clash: u64,
+        payment_usd: u64,
+        payment_decimals: u8,
+        decimals: u8,
+    ) -> Result<(u64, u64), ProgramError> {
+        let payment = self
+            .fill_exact_out(clash, payment_usd, payment_decimals, decimals)?
+            .payment;
+
+        let usd = tokens_to_usd(payment, payment_usd, payment_decimals, Rounding::Down)?;
+
+        Ok((payment, usd))
+    }
+}
+
+impl PriceSegment {
+    pub fn flat(clash_usd: u64, length: u64, sold: u64) -> Self {
+        Self {
+            start_usd: clash_usd,
+            end_usd: clash_usd,
+            length,
+            sold,
+        }
+    }
+
+    pub fn available(&self) -> u64 {
+        self.length.saturating_sub(self.sold)
+    }
+
+    fn is_flat(&self) -> bool {
+        self.start_usd == self.end_usd
+    }
+
+    // Scaled price once `offset` base units of the segment are sold
+    fn scaled_price_at(&self, offset: u64) -> Result<u128, ProgramError> {
+        let overflow = || ico_error(ICOError::PricingOverflow);
+
+        let rise = mul_div(
+            self.end_usd.saturating_sub(self.start_usd) as u128,
+            (offset as u128)
+                .checked_mul(CURVE_PRICE_SCALE)
+                .ok_or_else(overflow)?,
+            self.length as u128,
+            Rounding::Up,
+        )?;
+
+        (self.start_usd as u128)
+            .checked_mul(CURVE_PRICE_SCALE)
+            .and_then(|start| start.checked_add(rise))
+            .ok_or_else(overflow)
+    }
+
+    // Payment owed for the next `clash` base units of the segment, rounded up in favour of
+    // the sale. A rising price is integrated exactly as the average of the prices at both
+    // ends of the purchase
+    fn cost(
+        &self,
+        clash: u64,
+        payment_usd: u64,
+        payment_decimals: u8,
+        decimals: u8,
+    ) -> Result<u64, ProgramError> {
+        if self.is_flat() {
+            return tokens_to_payment(
+                clash,
+                self.start_usd,
+                decimals,
+                payment_usd,
+                payment_decimals,
+            );
+        }
+
+        let overflow = || ico_error(ICOError::PricingOverflow);
+
+        let end = self.sold.checked_add(clash).ok_or_else(overflow)?;
+        let price_sum = self
+            .scaled_price_at(self.sold)?
+            .checked_add(self.scaled_price_at(end)?)
+            .ok_or_else(overflow)?;
+
+        let scaled_usd = mul_div(
+            clash as u128,
+            price_sum,
+            2 * token_unit(decimals)?,
+            Rounding::Up,
+        )?;
+
+        let denominator = CURVE_PRICE_SCALE
+            .checked_mul(payment_usd as u128)
+            .ok_or_else(overflow)?;
+
+        to_u64(mul_div(
+            scaled_usd,
+            token_unit(payment_decimals)?,
+            denominator,
+            Rounding::Up,
+        )?)
+    }
+
+    // Most base units of the segment a payment can buy, searched over the curve cost since
+    // it has no simple inverse
+    fn tokens_for(
+        &self,
+        payment: u64,
+        payment_usd: u64,
+        payment_decimals: u8,
+        decimals: u8,
+    ) -> Result<u64, ProgramError> {
+        let available = self.available();
+
+        if self.is_flat() {
+            let clash = payment_to_tokens(
+                payment,
+                payment_usd,
+                payment_decimals,
+                self.start_usd,
+                decimals,
+            )?;
+
+            return Ok(clash.min(available));
+        }
+
+        let (mut low, mut high) = (0, available);
+
+        while low < high {
+            let middle = low + (high - low) / 2 + 1;
+
+            if self.cost(middle, payment_usd, payment_decimals, decimals)? <= payment {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+
+        Ok(low)
+    }
+}
+
+impl Fill {
+    fn add(&mut self, clash: u64, payment: u64) -> Result<(), ProgramError> {
+        let overflow = || ico_error(ICOError::PricingOverflow);
+
+        self.clash = self.clash.checked_add(clash).ok_or_else(overflow)?;
+        self.payment = self.payment.checked_add(payment).ok_or_else(overflow)?;
+
+        Ok(())
+    }
+}
+
+// Lamports paid for a Dutch auction purchase weighted by the inverse of its price, so the
+// lamports it is worth at the clearing price are `clearing_usd * weight / scale` whatever
+// the SOL/USD price was. Rounded down so rebates never exceed the lamports paid
+pub fn auction_rebate_weight(lamports: u64, clash_usd: u64) -> Result<u128, ProgramError> {
+    mul_div(
+        lamports as u128,
+        REBATE_WEIGHT_SCALE,
+        clash_usd as u128,
+        Rounding::Down,
+    )
+}
+
+// Lamports worth keeping at the Dutch auction clearing price for a rebate weight
+pub fn auction_lamports_at_clearing(
+    rebate_weight: u128,
+    clearing_usd: u64,
+    rounding: Rounding,
+) -> Result<u64, ProgramError> {
+    to_u64(mul_div(
+        rebate_weight,
+        clearing_usd as u128,
+        REBATE_WEIGHT_SCALE,
+        rounding,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use solana_program::pubkey::Pubkey;
+
+    use crate::pricing::{ONE_USD, SOL_DECIMALS, USD_DECIMALS};
+    use crate::state::{CurvePoint, ICOSaleConfig, PriceRound, SaleStatus, SolPriceSource};
+
+    // CLASH has 6 decimals in every fixture below
+    const DECIMALS: u8 = 6;
+    const TOKEN: u64 = 1_000_000;
+
+    const SOL_USD: u64 = 150_000_000;
+
+    fn sale(rounds: Vec<PriceRound>) -> ICOProgramData {
+        let mut ico_data = ICOProgramData::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            0,
+            Pubkey::new_unique(),
+            ICOSaleConfig {
+                clash_usd: 10_000,
+                sol_usd: SOL_USD,
+                price_source: SolPriceSource::Fixed,
+                min_usd_price: 0,
+                max_usd_price: u64::MAX,
+                clash_sol_wallet: Pubkey::new_unique(),
+                clash_payment_authority: Pubkey::new_unique(),
+                accepted_quote_mints: vec![],
+                max_usd_per_wallet: 0,
+                allowlist_root: None,
+                start_timestamp: 0,
+                end_timestamp: 1_000,
+                extension_window_seconds: 0,
+                guardian: None,
+                soft_cap_usd: 0,
+                hard_cap_usd: 0,
+                rounds,
+                bonding_curve: vec![],
+                dutch_auction: None,
+                fair_launch: None,
+                batch_auction: None,
+                vesting: None,
+                tge_timestamp: None,
+            },
+        );
+        ico_data.status = SaleStatus::Active;
+        ico_data
+    }
+
+    fn round(clash_usd: u64, allocation: u64) -> PriceRound {
+        PriceRound {
+            clash_usd,
+            allocation,
+            start_timestamp: None,
+            end_timestamp: None,
+        }
+    }
+
+    // Seed round at 0.02 USD with 900 of its 1000 tokens sold, then a round at 0.04 USD
+    fn tiered_sale() -> ICOProgramData {
+        let mut ico_data = sale(vec![
+            round(20_000, 1_000 * TOKEN),
+            round(40_000, 1_000 * TOKEN),
+        ]);
+        ico_data.clash_sold = 900 * TOKEN;
+        ico_data.round_sold = 900 * TOKEN;
+        ico_data
+    }
+
+    #[test]
+    fn purchase_crossing_a_round_boundary() {
+        let schedule = PriceSchedule::load(&tiered_sale(), 500).unwrap();
+
+        // 100 tokens at 0.02 USD then 100 tokens at 0.04 USD
+        let fill = schedule
+            .fill_exact_out(200 * TOKEN, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 200 * TOKEN,
+                payment: 6 * ONE_USD,
+            }
+        );
+
+        let fill = schedule
+            .fill_exact_in(6 * ONE_USD, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 200 * TOKEN,
+                payment: 6 * ONE_USD,
+            }
+        );
+    }
+
+    #[test]
+    fn sol_purchase_crossing_a_round_boundary_rounds_in_favour_of_the_sale() {
+        let schedule = PriceSchedule::load(&tiered_sale(), 500).unwrap();
+
+        // 6 USD is 0.04 SOL: the first round costs 13_333_333.3 lamports, rounded up, and the
+        // 26_666_666 lamports left buy 99.9999975 tokens of the second round, rounded down
+        let fill = schedule
+            .fill_exact_in(40_000_000, SOL_USD, SOL_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 100 * TOKEN + 99_999_997,
+                payment: 40_000_000,
+            }
+        );
+
+        let fill = schedule
+            .fill_exact_out(200 * TOKEN, SOL_USD, SOL_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 200 * TOKEN,
+                payment: 13_333_334 + 26_666_667,
+            }
+        );
+    }
+
+    #[test]
+    fn purchase_partially_filled_by_the_last_round() {
+        let schedule = PriceSchedule::load(&tiered_sale(), 500).unwrap();
+
+        // Only 1100 tokens are left over both rounds, worth 2 + 40 USD
+        let fill = schedule
+            .fill_exact_in(100 * ONE_USD, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 1_100 * TOKEN,
+                payment: 42 * ONE_USD,
+            }
+        );
+
+        let fill = schedule
+            .fill_exact_out(2_000 * TOKEN, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 1_100 * TOKEN,
+                payment: 42 * ONE_USD,
+            }
+        );
+    }
+
+    #[test]
+    fn closed_and_pending_rounds_are_skipped() {
+        let mut ico_data = tiered_sale();
+        ico_data.config.rounds[0].end_timestamp = Some(400);
+        ico_data.config.rounds[1].start_timestamp = Some(600);
+
+        assert!(PriceSchedule::load(&ico_data, 500).is_err());
+
+        // Once the seed round closes the next one is sold from its first token
+        let schedule = PriceSchedule::load(&ico_data, 600).unwrap();
+        let fill = schedule
+            .fill_exact_out(100 * TOKEN, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 100 * TOKEN,
+                payment: 4 * ONE_USD,
+            }
+        );
+    }
+
+    #[test]
+    fn partial_fill_charges_only_the_tokens_left() {
+        let schedule = PriceSchedule::load(&sale(vec![]), 500).unwrap();
+
+        // 1 SOL buys 15000 tokens at 0.01 USD, only 1000 are left: they cost 10 USD, which is
+        // 66_666_666.6 lamports rounded up
+        assert_eq!(
+            schedule
+                .fill_exact_in(1_000_000_000, SOL_USD, SOL_DECIMALS, DECIMALS)
+                .unwrap()
+                .clash,
+            15_000 * TOKEN
+        );
+
+        let (payment, usd) = schedule
+            .partial_fill(1_000 * TOKEN, SOL_USD, SOL_DECIMALS, DECIMALS)
+            .unwrap();
+
+        assert_eq!(payment, 66_666_667);
+        assert_eq!(usd, 10 * ONE_USD);
+
+        // The payment charged buys back at least the tokens sold
+        assert!(
+            schedule
+                .fill_exact_in(payment, SOL_USD, SOL_DECIMALS, DECIMALS)
+                .unwrap()
+                .clash
+                >= 1_000 * TOKEN
+        );
+    }
+
+    fn point(clash_sold: u64, clash_usd: u64) -> CurvePoint {
+        CurvePoint {
+            clash_sold,
+            clash_usd,
+        }
+    }
+
+    // Curve rising from 0.01 to 0.03 USD over the first 1000 tokens then to 0.05 USD over
+    // the next 1000 tokens, with `clash_sold` tokens already sold
+    fn curve_sale(clash_sold: u64) -> ICOProgramData {
+        let mut ico_data = sale(vec![]);
+        ico_data.config.bonding_curve = vec![
+            point(0, 10_000),
+            point(1_000 * TOKEN, 30_000),
+            point(2_000 * TOKEN, 50_000),
+        ];
+        ico_data.clash_sold = clash_sold;
+        ico_data
+    }
+
+    #[test]
+    fn purchase_crossing_a_curve_point() {
+        let schedule = PriceSchedule::load(&curve_sale(500 * TOKEN), 500).unwrap();
+
+        // 500 tokens from 0.02 to 0.03 USD average 0.025 USD, then 500 tokens from 0.03 to
+        // 0.04 USD average 0.035 USD: 12.5 + 17.5 USD
+        let fill = schedule
+            .fill_exact_out(1_000 * TOKEN, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 1_000 * TOKEN,
+                payment: 30 * ONE_USD,
+            }
+        );
+
+        let fill = schedule
+            .fill_exact_in(30 * ONE_USD, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 1_000 * TOKEN,
+                payment: 30 * ONE_USD,
+            }
+        );
+    }
+
+    #[test]
+    fn purchase_within_a_curve_section_pays_its_average_price() {
+        let schedule = PriceSchedule::load(&curve_sale(0), 500).unwrap();
+
+        // 100 tokens from 0.01 to 0.012 USD average 0.011 USD
+        let fill = schedule
+            .fill_exact_out(100 * TOKEN, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 100 * TOKEN,
+                payment: 1_100_000,
+            }
+        );
+
+        // One micro USD short of the cost buys fewer tokens, never more
+        let fill = schedule
+            .fill_exact_in(1_099_999, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert!(fill.clash < 100 * TOKEN);
+        assert_eq!(fill.payment, 1_099_999);
+        assert!(
+            schedule
+                .fill_exact_out(fill.clash, ONE_USD, USD_DECIMALS, DECIMALS)
+                .unwrap()
+                .payment
+                <= 1_099_999
+        );
+    }
+
+    #[test]
+    fn purchase_partially_filled_by_the_last_curve_section() {
+        let schedule = PriceSchedule::load(&curve_sale(1_900 * TOKEN), 500).unwrap();
+
+        // Only 100 tokens are left, from 0.048 to 0.05 USD average 0.049 USD
+        let fill = schedule
+            .fill_exact_in(100 * ONE_USD, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 100 * TOKEN,
+                payment: 4_900_000,
+            }
+        );
+
+        let fill = schedule
+            .fill_exact_out(500 * TOKEN, ONE_USD, USD_DECIMALS, DECIMALS)
+            .unwrap();
+        assert_eq!(
+            fill,
+            Fill {
+                clash: 100 * TOKEN,
+                payment: 4_900_000,
+            }
+        );
+
+        // Nothing is left to sell once the curve is sold out
+        let schedule = PriceSchedule::load(&curve_sale(2_000 * TOKEN), 500).unwrap();
+        assert_eq!(
+            schedule
+                .fill_exact_in(100 * ONE_USD, ONE_USD, USD_DECIMALS, DECIMALS)
+                .unwrap(),
+            Fill::default()
+        );
+    }
+}
@@ -1,19 +1,2278 @@
-use solana_program::pubkey::Pubkey;
+use solana_program::{entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
 
 use borsh::{BorshDeserialize, BorshSerialize};
 
+use crate::error::{ico_error, ICOError};
+
+use crate::pricing::{format_amount, mul_div, to_u64, token_unit, Rounding, USD_DECIMALS};
+
+use crate::schedule::auction_lamports_at_clearing;
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone)]
+pub enum SolPriceSource {
+    // SOL/USD price is the `sol_usd` value stored in the sale config
+    Fixed,
+
+    // SOL/USD price is read from a Pyth price account on every exchange,
+    // a zero max age disables the corresponding staleness check
+    Pyth {
+        price_account: Pubkey,
+        max_age_slots: u64,
+        max_age_seconds: u64,
+        max_confidence_bps: u16,
+    },
+
+    // SOL/USD price is read from the program price feed written by `publisher`
+    PriceFeed {
+        publisher: Pubkey,
+        max_age_seconds: u64,
+    },
+}
+
+impl SolPriceSource {
+    pub const LEN: usize = 1 + 32 + 8 + 8 + 2;
+}
+
+// SPL token accepted as payment, e.g. USDC or USDT
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone)]
+pub struct QuoteMintConfig {
+    pub mint: Pubkey,
+
+    // Quote token price in micro USD per whole token, 1_000_000 for a USD stablecoin
+    pub usd_price: u64,
+}
+
+impl QuoteMintConfig {
+    pub const LEN: usize = 32 + 8;
+}
+
+// Price round of a tiered sale, selling up to `allocation` CLASH base units at `clash_usd`
+// micro USD per token. Rounds without a start open as soon as the previous one is over
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone)]
+pub struct PriceRound {
+    pub clash_usd: u64,
+    pub allocation: u64,
+    pub start_timestamp: Option<i64>,
+    pub end_timestamp: Option<i64>,
+}
+
+impl PriceRound {
+    pub const LEN: usize = 8 + 8 + 1 + 8 + 1 + 8;
+
+    pub fn has_started(&self, now: i64) -> bool {
+        !matches!(self.start_timestamp, Some(start) if now < start)
+    }
+
+    pub fn has_ended(&self, now: i64) -> bool {
+        matches!(self.end_timestamp, Some(end) if now >= end)
+    }
+}
+
+// Point of a bonding curve, the CLASH price is `clash_usd` micro USD per token once
+// `clash_sold` base units are sold and moves linearly between consecutive points
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone)]
+pub struct CurvePoint {
+    pub clash_sold: u64,
+    pub clash_usd: u64,
+}
+
+impl CurvePoint {
+    pub const LEN: usize = 8 + 8;
+}
+
+// Descending price auction from `start_usd` down to `floor_usd` micro USD per token over
+// `duration_seconds` after the sale start, decaying continuously or every `step_seconds`.
+// With `rebate` SOL is escrowed and buyers get back what they paid above the clearing price
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct DutchAuction {
+    pub start_usd: u64,
+    pub floor_usd: u64,
+    pub duration_seconds: u64,
+    pub step_seconds: u64,
+    pub rebate: bool,
+}
+
+impl DutchAuction {
+    pub const LEN: usize = 8 + 8 + 8 + 8 + 1;
+
+    // Auction price at `now`, the decay is rounded down so the price is rounded up
+    pub fn price_at(&self, start_timestamp: i64, now: i64) -> u64 {
+        let elapsed = now.saturating_sub(start_timestamp).max(0) as u64;
+
+        if elapsed >= self.duration_seconds {
+            return self.floor_usd;
+        }
+
+        let elapsed = match self.step_seconds {
+            0 => elapsed,
+            step_seconds => elapsed - elapsed % step_seconds,
+        };
+
+        // Subtractions saturate so a config stored without a valid price range cannot underflow
+        let decay = self.start_usd.saturating_sub(self.floor_usd) as u128 * elapsed as u128
+            / self.duration_seconds as u128;
+
+        self.start_usd.saturating_sub(decay as u64)
+    }
+}
+
+// Release schedule of purchased tokens: `tge_unlock_bps` of them unlock at `tge_timestamp`
+// and the rest vests linearly over `duration_seconds` once `cliff_seconds` have passed
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct VestingSchedule {
+    pub tge_timestamp: i64,
+    pub tge_unlock_bps: u16,
+    pub cliff_seconds: u64,
+    pub duration_seconds: u64,
+}
+
+impl VestingSchedule {
+    pub const LEN: usize = 8 + 2 + 8 + 8;
+    pub const MAX_BPS: u16 = 10_000;
+
+    // Tokens vested out of `total` at `now`, rounded down
+    pub fn vested(&self, total: u64, now: i64) -> Result<u64, ProgramError> {
+        if now < self.tge_timestamp {
+            return Ok(0);
+        }
+
+        let unlocked = to_u64(mul_div(
+            total as u128,
+            self.tge_unlock_bps as u128,
+            Self::MAX_BPS as u128,
+            Rounding::Down,
+        )?)?;
+
+        let cliff_end = self
+            .tge_timestamp
+            .saturating_add(self.cliff_seconds.min(i64::MAX as u64) as i64);
+
+        if now < cliff_end {
+            return Ok(unlocked);
+        }
+
+        let elapsed = (now - cliff_end) as u64;
+
+        if elapsed >= self.duration_seconds {
+            return Ok(total);
+        }
+
+        let linear = mul_div(
+            (total - unlocked) as u128,
+            elapsed as u128,
+            self.duration_seconds as u128,
+            Rounding::Down,
+        )?;
+
+        Ok(unlocked + to_u64(linear)?)
+    }
+}
+
+// Commit phase selling a fixed `allocation` of CLASH base units for `raise_lamports`. When
+// oversubscribed every commitment is filled pro rata and the unused lamports are refunded
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct FairLaunch {
+    pub allocation: u64,
+    pub raise_lamports: u64,
+
+    // Max lamports a wallet can commit over the whole sale, zero for no cap
+    pub max_commit_lamports: u64,
+}
+
+impl FairLaunch {
+    pub const LEN: usize = 8 + 8 + 8;
+
+    // Lamports raised out of the total committed, the rest is refunded
+    pub fn raised_lamports(&self, committed_lamports: u64) -> u64 {
+        committed_lamports.min(self.raise_lamports)
+    }
+
+    // Tokens received and lamports spent by a commitment once the commit phase is over.
+    // Tokens are rounded down and lamports spent up so the sale never owes more than it holds
+    pub fn settle(
+        &self,
+        commitment: u64,
+        committed_lamports: u64,
+    ) -> Result<(u64, u64), ProgramError> {
+        let filled_lamports = committed_lamports.max(self.raise_lamports) as u128;
+
+        let clash = mul_div(
+            commitment as u128,
+            self.allocation as u128,
+            filled_lamports,
+            Rounding::Down,
+        )?;
+        let spent_lamports = mul_div(
+            commitment as u128,
+            self.raise_lamports as u128,
+            filled_lamports,
+            Rounding::Up,
+        )?;
+
+        Ok((to_u64(clash)?, to_u64(spent_lamports)?.min(commitment)))
+    }
+}
+
+// Sealed bid auction of `supply` CLASH base units. Bids are committed during the sale
+// window, revealed until `reveal_end_timestamp` and all winners pay the same clearing
+// price. Bid prices are in lamports per whole token, `tick_lamports` apart from the min price
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct BatchAuction {
+    pub supply: u64,
+    pub min_price_lamports: u64,
+    pub tick_lamports: u64,
+    pub reveal_end_timestamp: i64,
+}
+
+impl BatchAuction {
+    pub const LEN: usize = 8 + 8 + 8 + 8;
+    pub const MAX_PRICE_TICKS: usize = 32;
+
+    pub fn tick_price(&self, tick: usize) -> u64 {
+        self.min_price_lamports + self.tick_lamports * tick as u64
+    }
+
+    // Price tick of a bid, `None` if the price is not on a tick
+    pub fn price_tick(&self, price_lamports: u64) -> Option<usize> {
+        let offset = price_lamports.checked_sub(self.min_price_lamports)?;
+
+        if offset % self.tick_lamports != 0 {
+            return None;
+        }
+
+        let tick = offset / self.tick_lamports;
+
+        if tick >= Self::MAX_PRICE_TICKS as u64 {
+            return None;
+        }
+
+        Some(tick as usize)
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ICOSaleConfig {
+    // Clash token price in micro USD per whole token
+    pub clash_usd: u64,
+
+    // Solana native token price in micro USD per SOL and where to read it from
+    pub sol_usd: u64,
+    pub price_source: SolPriceSource,
+
+    // Min and max values in micro USD to buy during an ICO sale
+    pub min_usd_price: u64,
+    pub max_usd_price: u64,
+
+    // Accounts to receive SOL and to sign payments realized off chain
+    pub clash_sol_wallet: Pubkey,
+    pub clash_payment_authority: Pubkey,
+
+    // SPL tokens accepted as payment along with SOL, paid into token accounts
+    // owned by `clash_sol_wallet`
+    pub accepted_quote_mints: Vec<QuoteMintConfig>,
+
+    // Max value in micro USD a wallet can buy over the whole sale, zero for no cap
+    pub max_usd_per_wallet: u64,
+
+    // Merkle root of the wallets allowed to buy, `None` for a public sale
+    pub allowlist_root: Option<[u8; 32]>,
+
+    // Unix timestamps bounding the sale, purchases landing within the last
+    // `extension_window_seconds` push the end to that many seconds after them
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub extension_window_seconds: u64,
+
+    // Key allowed to pause the sale along with the initializer, it cannot resume it
+    pub guardian: Option<Pubkey>,
+
+    // Min value in micro USD to raise, zero to disable. With a soft cap SOL is escrowed in
+    // the sale vault and tokens are claimed once the sale is finalized, or SOL is refunded
+    // if the cap is missed
+    pub soft_cap_usd: u64,
+
+    // Max value in micro USD to raise, zero for no cap. The purchase reaching the cap is
+    // partially filled when possible and the sale ends right after it
+    pub hard_cap_usd: u64,
+
+    // Price rounds sold in order, each one replacing `clash_usd` while it is open.
+    // Empty for a fixed price sale
+    pub rounds: Vec<PriceRound>,
+
+    // Piecewise linear price over the tokens sold, starting at zero tokens sold. The sale
+    // sells out at the last point. Empty for a fixed price or tiered sale
+    pub bonding_curve: Vec<CurvePoint>,
+
+    // Prices the sale as a Dutch auction starting at `start_timestamp` instead of `clash_usd`
+    pub dutch_auction: Option<DutchAuction>,
+
+    // Sells a fixed allocation pro rata to the SOL committed instead of through exchanges
+    pub fair_launch: Option<FairLaunch>,
+
+    // Sells a fixed supply through sealed bids at a uniform clearing price
+    pub batch_auction: Option<BatchAuction>,
+
+    // Holds purchased tokens in the buyer records and releases them on claims as they vest,
+    // `None` to transfer them on purchase
+    pub vesting: Option<VestingSchedule>,
+
+    // Holds purchased tokens in the buyer records until they are claimed after this unix
+    // timestamp, `None` to transfer them on purchase
+    pub tge_timestamp: Option<i64>,
+}
+
+impl ICOSaleConfig {
+    pub const MAX_QUOTE_MINTS: usize = 4;
+    pub const MAX_ROUNDS: usize = 8;
+    pub const MAX_CURVE_POINTS: usize = 8;
+
+    pub const LEN: usize = 8
+        + 8
+        + SolPriceSource::LEN
+        + 8
+        + 8
+        + 32
+        + 32
+        + 4
+        + Self::MAX_QUOTE_MINTS * QuoteMintConfig::LEN
+        + 8
+        + 1
+        + 32
+        + 8
+        + 8
+        + 8
+        + 1
+        + 32
+        + 8
+        + 8
+        + 4
+        + Self::MAX_ROUNDS * PriceRound::LEN
+        + 4
+        + Self::MAX_CURVE_POINTS * CurvePoint::LEN
+        + 1
+        + DutchAuction::LEN
+        + 1
+        + FairLaunch::LEN
+        + 1
+        + BatchAuction::LEN
+        + 1
+        + VestingSchedule::LEN
+        + 1
+        + 8;
+
+    // Extends the sale end when a purchase lands within the extension window,
+    // returns the new end timestamp if it changed
+    pub fn extend_sale_window(&mut self, now: i64) -> Option<i64> {
+        let extended_end = now.saturating_add(self.extension_window_seconds as i64);
+
+        if self.extension_window_seconds == 0 || extended_end <= self.end_timestamp {
+            return None;
+        }
+
+        self.end_timestamp = extended_end;
+
+        Some(extended_end)
+    }
+}
+
+// Sale lifecycle, moving from Pending to Active and from Active to Ended follows the sale
+// time window and is applied by the first instruction observing it
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SaleStatus {
+    Pending,
+    Active,
+    Paused,
+    Ended,
+    Finalized,
+    Refunding,
+    Cancelled,
+}
+
+impl SaleStatus {
+    pub fn can_transition_to(self, next: SaleStatus) -> bool {
+        use SaleStatus::*;
+
+        matches!(
+            (self, next),
+            (Pending, Active)
+                | (Pending, Paused)
+                | (Pending, Cancelled)
+                | (Active, Paused)
+                | (Active, Ended)
+                | (Active, Cancelled)
+                | (Paused, Pending)
+                | (Paused, Active)
+                | (Paused, Ended)
+                | (Paused, Cancelled)
+                | (Ended, Finalized)
+                | (Ended, Refunding)
+        )
+    }
+}
+
 #[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
 pub struct ICOProgramData {
     pub initializer: Pubkey,
     pub initializer_ata: Pubkey,
+    pub sale_id: u64,
+    pub mint: Pubkey,
+    pub status: SaleStatus,
+
+    // Running totals over every purchase, lamports only count SOL payments
+    pub raised_usd: u64,
+    pub lamports_raised: u64,
+    pub clash_sold: u64,
+    pub buyer_count: u64,
+
+    // Price round being sold and tokens sold in it, for tiered sales
+    pub current_round: u8,
+    pub round_sold: u64,
+
+    // Lamports held in the vault and tokens owed to buyers of a soft cap sale, or lamports
+    // escrowed by buyers of a Dutch auction with rebates who have not claimed them yet
+    pub escrowed_lamports: u64,
+    pub claimable_clash: u64,
+
+    // Lowest price paid in a Dutch auction and the weight of the lamports escrowed for
+    // rebates, see `auction_rebate_weight`
+    pub clearing_usd: u64,
+    pub rebate_weight: u128,
+
+    // Lamports committed to a fair launch and those whose buyers have not settled yet
+    pub committed_lamports: u64,
+    pub unsettled_lamports: u64,
+
+    // Tokens bid at each price tick of a batch auction once revealed, then its clearing
+    // price in lamports per whole token and the tokens shared by the bids at that price
+    pub bid_demand: [u64; BatchAuction::MAX_PRICE_TICKS],
+    pub clearing_lamports: u64,
+    pub marginal_supply: u64,
+
+    // Purchased tokens vesting for buyers and not released yet
+    pub vesting_clash: u64,
+
+    pub config: ICOSaleConfig,
+}
+
+impl ICOProgramData {
+    pub const LEN: usize = 32
+        + 32
+        + 8
+        + 32
+        + 1
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 8
+        + 8
+        + 8
+        + 8
+        + 16
+        + 8
+        + 8
+        + BatchAuction::MAX_PRICE_TICKS * 8
+        + 8
+        + 8
+        + 8
+        + ICOSaleConfig::LEN;
+
+    // Pending sale with no purchases yet
+    pub fn new(
+        initializer: Pubkey,
+        initializer_ata: Pubkey,
+        sale_id: u64,
+        mint: Pubkey,
+        config: ICOSaleConfig,
+    ) -> Self {
+        Self {
+            initializer,
+            initializer_ata,
+            sale_id,
+            mint,
+            status: SaleStatus::Pending,
+            raised_usd: 0,
+            lamports_raised: 0,
+            clash_sold: 0,
+            buyer_count: 0,
+            current_round: 0,
+            round_sold: 0,
+            escrowed_lamports: 0,
+            claimable_clash: 0,
+            clearing_usd: 0,
+            rebate_weight: 0,
+            committed_lamports: 0,
+            unsettled_lamports: 0,
+            bid_demand: [0; BatchAuction::MAX_PRICE_TICKS],
+            clearing_lamports: 0,
+            marginal_supply: 0,
+            vesting_clash: 0,
+            config,
+        }
+    }
+
+    // Account data is sized for the largest config, so trailing bytes are ignored
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    // Moves the sale to a new status, every transition is logged
+    pub fn set_status(&mut self, status: SaleStatus) -> ProgramResult {
+        if !self.status.can_transition_to(status) {
+            msg!(format!(
+                "Sale status cannot change from {:?} to {:?}",
+                self.status, status
+            )
+            .as_str());
+
+            return Err(ico_error(ICOError::InvalidSaleStatusTransition));
+        }
+
+        msg!(format!("Sale status changed: {:?} -> {:?}", self.status, status).as_str());
+
+        self.status = status;
+
+        Ok(())
+    }
+
+    // Applies the transitions due to the sale time window, returns whether the status changed
+    pub fn sync_status(&mut self, now: i64) -> Result<bool, ProgramError> {
+        let status = self.status;
+
+        if self.status == SaleStatus::Pending && now >= self.config.start_timestamp {
+            self.set_status(SaleStatus::Active)?;
+        }
+
+        if matches!(self.status, SaleStatus::Active | SaleStatus::Paused)
+            && now >= self.config.end_timestamp
+        {
+            self.set_status(SaleStatus::Ended)?;
+        }
+
+        Ok(self.status != status)
+    }
+
+    // Value in micro USD left to raise before the hard cap, `None` without a hard cap
+    pub fn remaining_usd(&self) -> Option<u64> {
+        match self.config.hard_cap_usd {
+            0 => None,
+            hard_cap_usd => Some(hard_cap_usd.saturating_sub(self.raised_usd)),
+        }
+    }
+
+    // Value left below the minimum purchase cannot be bought, so it ends the sale as well
+    pub fn hard_cap_reached(&self) -> bool {
+        matches!(
+            self.remaining_usd(),
+            Some(remaining_usd) if remaining_usd == 0 || remaining_usd < self.config.min_usd_price
+        )
+    }
+
+    // Whether any buyer has purchased, committed or bid, or lamports are escrowed
+    pub fn has_buyers(&self) -> bool {
+        self.buyer_count > 0 || self.escrowed_lamports > 0
+    }
+
+    // Buyers of a cancelled sale get back everything they escrowed while none of their tokens
+    // was delivered: soft cap sales hold them, fair launches and batch auctions settle later
+    pub fn refunds_on_cancel(&self) -> bool {
+        self.config.soft_cap_usd > 0
+            || self.config.fair_launch.is_some()
+            || self.config.batch_auction.is_some()
+    }
+
+    // Current Dutch auction price, `None` if the sale is not an auction
+    pub fn auction_price(&self, now: i64) -> Option<u64> {
+        self.config
+            .dutch_auction
+            .map(|auction| auction.price_at(self.config.start_timestamp, now))
+    }
+
+    pub fn has_auction_rebate(&self) -> bool {
+        matches!(self.config.dutch_auction, Some(auction) if auction.rebate)
+    }
+
+    // The clearing price of a Dutch auction is the lowest price paid
+    pub fn record_auction_price(&mut self, clash_usd: u64) {
+        if self.clearing_usd == 0 || clash_usd < self.clearing_usd {
+            self.clearing_usd = clash_usd;
+        }
+    }
+
+    pub fn add_escrow(&mut self, lamports: u64, clash: u64, rebate_weight: u128) -> ProgramResult {
+        let overflow = || ico_error(ICOError::PricingOverflow);
+
+        self.escrowed_lamports = self
+            .escrowed_lamports
+            .checked_add(lamports)
+            .ok_or_else(overflow)?;
+        self.claimable_clash = self
+            .claimable_clash
+            .checked_add(clash)
+            .ok_or_else(overflow)?;
+        self.rebate_weight = self
+            .rebate_weight
+            .checked_add(rebate_weight)
+            .ok_or_else(overflow)?;
+
+        Ok(())
+    }
+
+    // Settles an ended sale: it is finalized when it reached its soft cap and buyers are
+    // refunded otherwise. Returns the lamports the vault releases to the team wallet
+    pub fn finalize(&mut self) -> Result<u64, ProgramError> {
+        if self.raised_usd < self.config.soft_cap_usd {
+            // Buyers reclaim their lamports, tokens stay with the sale
+            self.set_status(SaleStatus::Refunding)?;
+
+            return Ok(0);
+        }
+
+        self.set_status(SaleStatus::Finalized)?;
+
+        // Dutch auctions with rebates only release the lamports purchases are worth at the
+        // clearing price, the rest is claimed back by buyers. Fair launches release up to their
+        // SOL target, oversubscribed commitments being refunded on settlement
+        if self.has_auction_rebate() {
+            return auction_lamports_at_clearing(
+                self.rebate_weight,
+                self.clearing_usd,
+                Rounding::Down,
+            );
+        }
+
+        if let Some(fair_launch) = self.config.fair_launch {
+            self.lamports_raised = fair_launch.raised_lamports(self.committed_lamports);
+
+            return Ok(self.lamports_raised);
+        }
+
+        if self.config.batch_auction.is_some() {
+            // Bids pay the clearing price to the team wallet as they are settled
+            self.clear_batch_auction()?;
+
+            return Ok(0);
+        }
+
+        let released_lamports = self.escrowed_lamports;
+
+        self.escrowed_lamports = 0;
+
+        Ok(released_lamports)
+    }
+
+    // Clears the escrow of a Dutch auction buyer once the sale is finalized, returns the
+    // lamports paid above the clearing price. What the purchases are worth at the clearing
+    // price is rounded up, so rebates never exceed what `finalize` left in the vault
+    pub fn release_rebate(&mut self, buyer_record: &mut BuyerRecord) -> Result<u64, ProgramError> {
+        let escrowed_lamports = buyer_record.escrowed_lamports;
+
+        let kept_lamports = auction_lamports_at_clearing(
+            buyer_record.rebate_weight,
+            self.clearing_usd,
+            Rounding::Up,
+        )?
+        .min(escrowed_lamports);
+
+        msg!(format!(
+            "Buyer escrowed {} lamports, worth {} lamports at the clearing price of {}USD",
+            escrowed_lamports,
+            kept_lamports,
+            format_amount(self.clearing_usd, USD_DECIMALS)
+        )
+        .as_str());
+
+        self.escrowed_lamports = self.escrowed_lamports.saturating_sub(escrowed_lamports);
+        self.rebate_weight = self
+            .rebate_weight
+            .saturating_sub(buyer_record.rebate_weight);
+
+        buyer_record.escrowed_lamports = 0;
+        buyer_record.rebate_weight = 0;
+
+        Ok(escrowed_lamports - kept_lamports)
+    }
+
+    // Clears the escrow of a refunded buyer, returns the lamports sent back to the buyer
+    pub fn refund_escrow(&mut self, buyer_record: &mut BuyerRecord) -> u64 {
+        let lamports = buyer_record.escrowed_lamports;
+
+        self.escrowed_lamports = self.escrowed_lamports.saturating_sub(lamports);
+        buyer_record.escrowed_lamports = 0;
+
+        // The bid of a cancelled batch auction is dropped with its escrow
+        if self.status == SaleStatus::Cancelled {
+            buyer_record.bid = None;
+        }
+
+        // Tokens held for the buyer are released back to the sale, unless they were bought in
+        // a fair launch
+        if self.config.fair_launch.is_none() {
+            self.claimable_clash = self
+                .claimable_clash
+                .saturating_sub(buyer_record.claimable_clash);
+            buyer_record.claimable_clash = 0;
+        }
+
+        lamports
+    }
+
+    // Releases the tokens held for a buyer and those vested at `now`, returns the tokens to
+    // transfer to the buyer. Nothing is released before the TGE of sales deferring it
+    pub fn release_claim(
+        &mut self,
+        buyer_record: &mut BuyerRecord,
+        now: i64,
+    ) -> Result<u64, ProgramError> {
+        if let Some(tge_timestamp) = self.config.tge_timestamp {
+            if now < tge_timestamp {
+                msg!(format!("Tokens can be claimed from {}", tge_timestamp).as_str());
+
+                return Err(ico_error(ICOError::ClaimBeforeTGE));
+            }
+        }
+
+        let claimable_clash = buyer_record.claimable_clash;
+        let vested_clash = match &self.config.vesting {
+            Some(vesting) => {
+                let vested_clash = buyer_record.releasable_clash(vesting, now)?;
+
+                msg!(format!(
+                    "{} of {} CLASH base units vesting for the buyer are releasable, {} already released",
+                    vested_clash, buyer_record.vesting_clash, buyer_record.released_clash
+                )
+                .as_str());
+
+                vested_clash
+            }
+            None => 0,
+        };
+
+        let clash = claimable_clash
+            .checked_add(vested_clash)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+        self.claimable_clash = self.claimable_clash.saturating_sub(claimable_clash);
+        buyer_record.claimable_clash = 0;
+
+        self.vesting_clash = self.vesting_clash.saturating_sub(vested_clash);
+        buyer_record.released_clash = buyer_record
+            .released_clash
+            .checked_add(vested_clash)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+        Ok(clash)
+    }
+
+    // Adds a purchase to the sale running totals. The sale ends once its hard cap is reached
+    pub fn add_purchase(
+        &mut self,
+        lamports: u64,
+        usd: u64,
+        clash: u64,
+        new_buyer: bool,
+    ) -> ProgramResult {
+        let overflow = || ico_error(ICOError::PricingOverflow);
+
+        self.raised_usd = self.raised_usd.checked_add(usd).ok_or_else(overflow)?;
+        self.lamports_raised = self
+            .lamports_raised
+            .checked_add(lamports)
+            .ok_or_else(overflow)?;
+        self.clash_sold = self.clash_sold.checked_add(clash).ok_or_else(overflow)?;
+
+        if new_buyer {
+            self.buyer_count = self.buyer_count.checked_add(1).ok_or_else(overflow)?;
+        }
+
+        msg!(format!(
+            "Sale totals: {} micro USD, {} lamports, {} CLASH base units, {} buyers",
+            self.raised_usd, self.lamports_raised, self.clash_sold, self.buyer_count
+        )
+        .as_str());
+
+        let curve_sold_out = matches!(
+            self.config.bonding_curve.last(),
+            Some(point) if self.clash_sold >= point.clash_sold
+        );
+
+        if curve_sold_out && self.status == SaleStatus::Active {
+            msg!("Sale bonding curve is sold out");
+
+            self.set_status(SaleStatus::Ended)?;
+        }
+
+        if self.hard_cap_reached() && self.status == SaleStatus::Active {
+            msg!(format!(
+                "Sale hard cap of {} micro USD reached",
+                self.config.hard_cap_usd
+            )
+            .as_str());
+
+            self.set_status(SaleStatus::Ended)?;
+        }
+
+        Ok(())
+    }
+
+    // Tokens are held as claimable by soft cap sales until they are finalized and by sales
+    // deferring their distribution to the TGE
+    pub fn holds_tokens(&self) -> bool {
+        self.config.soft_cap_usd > 0 || self.config.tge_timestamp.is_some()
+    }
+
+    // Tokens of the sale account owed to buyers, they cannot be sold again
+    pub fn held_clash(&self) -> u64 {
+        self.claimable_clash.saturating_add(self.vesting_clash)
+    }
+
+    pub fn add_vesting(&mut self, clash: u64) -> ProgramResult {
+        self.vesting_clash = self
+            .vesting_clash
+            .checked_add(clash)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+        Ok(())
+    }
+
+    pub fn add_commitment(&mut self, lamports: u64, new_buyer: bool) -> ProgramResult {
+        let overflow = || ico_error(ICOError::PricingOverflow);
+
+        self.committed_lamports = self
+            .committed_lamports
+            .checked_add(lamports)
+            .ok_or_else(overflow)?;
+        self.unsettled_lamports = self
+            .unsettled_lamports
+            .checked_add(lamports)
+            .ok_or_else(overflow)?;
+
+        if new_buyer {
+            self.buyer_count = self.buyer_count.checked_add(1).ok_or_else(overflow)?;
+        }
+
+        msg!(format!(
+            "Sale totals: {} lamports committed by {} buyers",
+            self.committed_lamports, self.buyer_count
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    // Turns the commitment of a fair launch buyer into tokens to claim and lamports to
+    // refund, once the sale is finalized
+    pub fn settle_commitment(&mut self, buyer_record: &mut BuyerRecord) -> ProgramResult {
+        let fair_launch = match self.config.fair_launch {
+            Some(fair_launch) => fair_launch,
+            None => return Ok(()),
+        };
+
+        let commitment = buyer_record.committed_lamports;
+
+        if commitment == 0 {
+            return Ok(());
+        }
+
+        let (clash, spent_lamports) = fair_launch.settle(commitment, self.committed_lamports)?;
+        let refund_lamports = commitment - spent_lamports;
+
+        msg!(format!(
+            "Buyer committed {} lamports, receives {} CLASH base units for {} lamports and is refunded {} lamports",
+            commitment, clash, spent_lamports, refund_lamports
+        )
+        .as_str());
+
+        buyer_record.lamports_paid = buyer_record
+            .lamports_paid
+            .checked_add(spent_lamports)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+        buyer_record.clash_received = buyer_record
+            .clash_received
+            .checked_add(clash)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+        buyer_record.committed_lamports = 0;
+        buyer_record.add_escrow(refund_lamports, clash, 0)?;
+
+        self.unsettled_lamports = self.unsettled_lamports.saturating_sub(commitment);
+        self.clash_sold = self
+            .clash_sold
+            .checked_add(clash)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+        self.add_escrow(refund_lamports, clash, 0)
+    }
+
+    // Computes the uniform clearing price of a batch auction from the revealed demand, the
+    // lowest price at which the bids from the highest price down cover the supply. When they
+    // never do every revealed bid wins at the lowest price bid
+    pub fn clear_batch_auction(&mut self) -> ProgramResult {
+        let batch_auction = match self.config.batch_auction {
+            Some(batch_auction) => batch_auction,
+            None => return Ok(()),
+        };
+
+        let mut filled = 0u64;
+
+        for tick in (0..BatchAuction::MAX_PRICE_TICKS).rev() {
+            let demand = self.bid_demand[tick];
+
+            if demand == 0 {
+                continue;
+            }
+
+            let remaining = batch_auction.supply - filled;
+
+            self.clearing_lamports = batch_auction.tick_price(tick);
+            self.marginal_supply = demand.min(remaining);
+
+            if demand >= remaining {
+                break;
+            }
+
+            filled += demand;
+        }
+
+        msg!(format!(
+            "Batch auction cleared at {} lamports per CLASH, {} CLASH base units shared at that price",
+            self.clearing_lamports, self.marginal_supply
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    // Tokens won by a revealed bid at the clearing price, bids at the clearing price share
+    // what is left of the supply pro rata and rounded down
+    pub fn bid_fill(&self, bid: &SealedBid) -> Result<u64, ProgramError> {
+        let batch_auction = match self.config.batch_auction {
+            Some(batch_auction) => batch_auction,
+            None => return Ok(0),
+        };
+
+        if !bid.revealed
+            || self.clearing_lamports == 0
+            || bid.price_lamports < self.clearing_lamports
+        {
+            return Ok(0);
+        }
+
+        if bid.price_lamports > self.clearing_lamports {
+            return Ok(bid.quantity);
+        }
+
+        let demand = batch_auction
+            .price_tick(bid.price_lamports)
+            .map(|tick| self.bid_demand[tick])
+            .unwrap_or(0);
+
+        to_u64(mul_div(
+            bid.quantity as u128,
+            self.marginal_supply as u128,
+            demand as u128,
+            Rounding::Down,
+        )?)
+    }
+
+    // Turns the whole commitment of a fair launch buyer into lamports to refund, once the
+    // sale is cancelled
+    pub fn cancel_commitment(&mut self, buyer_record: &mut BuyerRecord) -> ProgramResult {
+        let commitment = buyer_record.committed_lamports;
+
+        if commitment == 0 {
+            return Ok(());
+        }
+
+        buyer_record.committed_lamports = 0;
+        buyer_record.add_escrow(commitment, 0, 0)?;
+
+        self.unsettled_lamports = self.unsettled_lamports.saturating_sub(commitment);
+        self.add_escrow(commitment, 0, 0)
+    }
+
+    // Moves a tiered sale through its rounds as their allocation sells out or their window
+    // closes, `clash` being the tokens just sold in the open rounds. The sale ends once
+    // every round is over
+    pub fn advance_rounds(&mut self, clash: u64, now: i64) -> ProgramResult {
+        let mut clash_left = clash;
+
+        while let Some(round) = self.config.rounds.get(self.current_round as usize) {
+            let available = round.allocation.saturating_sub(self.round_sold);
+
+            if !round.has_ended(now) && available > 0 {
+                if clash_left == 0 {
+                    return Ok(());
+                }
+
+                let sold = clash_left.min(available);
+
+                self.round_sold += sold;
+                clash_left -= sold;
+
+                continue;
+            }
+
+            self.current_round += 1;
+            self.round_sold = 0;
+
+            if let Some(round) = self.config.rounds.get(self.current_round as usize) {
+                msg!(format!(
+                    "Sale moved to price round {} at {} micro USD per CLASH",
+                    self.current_round, round.clash_usd
+                )
+                .as_str());
+            }
+        }
+
+        if !self.config.rounds.is_empty() && self.status == SaleStatus::Active {
+            msg!("Every price round of the sale is over");
+
+            self.set_status(SaleStatus::Ended)?;
+        }
+
+        Ok(())
+    }
+
+    // The initializer can pause and resume the sale, the guardian can only pause it
+    pub fn require_pause_authority(&self, authority: &Pubkey, paused: bool) -> ProgramResult {
+        let is_initializer = &self.initializer == authority;
+        let is_guardian = self.config.guardian.as_ref() == Some(authority);
+
+        if paused && !is_initializer && !is_guardian {
+            return Err(ico_error(ICOError::InvalidPauseAuthority));
+        }
+
+        if !paused && !is_initializer {
+            return Err(ico_error(ICOError::InitializerAccountMismatch));
+        }
+
+        Ok(())
+    }
+
+    // Rejects instructions not allowed in the current sale status
+    pub fn require_status(&self, allowed: &[SaleStatus]) -> ProgramResult {
+        if !allowed.contains(&self.status) {
+            msg!(format!(
+                "Sale is {:?}, instruction requires one of {:?}",
+                self.status, allowed
+            )
+            .as_str());
+
+            return Err(ico_error(ICOError::InvalidSaleStatus));
+        }
+
+        Ok(())
+    }
+}
+
+// Program owned SOL/USD quote, value is `price * 10^expo` USD
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct PriceFeed {
+    pub publisher: Pubkey,
+    pub price: i64,
+    pub expo: i32,
+    pub publish_slot: u64,
+    pub publish_timestamp: i64,
+}
+
+impl PriceFeed {
+    pub const LEN: usize = 32 + 8 + 4 + 8 + 8;
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct PriceFeedUpdateData {
+    pub price: i64,
+    pub expo: i32,
+}
+
+// Purchases of a buyer in a sale, created on its first purchase to enforce the per wallet cap
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct BuyerRecord {
+    pub sale: Pubkey,
+    pub buyer: Pubkey,
+
+    // Lamports only count SOL payments, USD value counts every payment
+    pub lamports_paid: u64,
+    pub usd_paid: u64,
+    pub clash_received: u64,
+
+    // Soft cap sales hold the buyer lamports and tokens until the sale is finalized, Dutch
+    // auctions with rebates hold the lamports until the buyer claims its rebate
+    pub escrowed_lamports: u64,
+    pub claimable_clash: u64,
+    pub rebate_weight: u128,
+
+    // Lamports committed to a fair launch and not settled yet
+    pub committed_lamports: u64,
+
+    // Batch auction bid of the buyer until it is settled
+    pub bid: Option<SealedBid>,
+
+    // Purchased tokens under the sale vesting schedule and those already released
+    pub vesting_clash: u64,
+    pub released_clash: u64,
+}
+
+impl BuyerRecord {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 8 + 16 + 8 + 1 + SealedBid::LEN + 8 + 8;
+
+    pub fn add_vesting(&mut self, clash: u64) -> ProgramResult {
+        self.vesting_clash = self
+            .vesting_clash
+            .checked_add(clash)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+        Ok(())
+    }
+
+    // Vested tokens not released yet
+    pub fn releasable_clash(
+        &self,
+        vesting: &VestingSchedule,
+        now: i64,
+    ) -> Result<u64, ProgramError> {
+        Ok(vesting
+            .vested(self.vesting_clash, now)?
+            .saturating_sub(self.released_clash))
+    }
+
+    // Account data is sized for a sealed bid, so trailing bytes are ignored
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    pub fn add_commitment(&mut self, lamports: u64, max_commit_lamports: u64) -> ProgramResult {
+        let committed_lamports = self
+            .committed_lamports
+            .checked_add(lamports)
+            .ok_or_else(|| ico_error(ICOError::PricingOverflow))?;
+
+        if max_commit_lamports > 0 && committed_lamports > max_commit_lamports {
+            msg!(format!(
+                "Wallet `{}` already committed {} lamports, cap is {} lamports",
+                self.buyer, self.committed_lamports, max_commit_lamports
+            )
+            .as_str());
+
+            return Err(ico_error(ICOError::CommitmentCapExceeded));
+        }
+
+        self.committed_lamports = committed_lamports;
+
+        Ok(())
+    }
+
+    pub fn add_escrow(&mut self, lamports: u64, clash: u64, rebate_weight: u128) -> ProgramResult {
+        let overflow = || ico_error(ICOError::PricingOverflow);
+
+        self.escrowed_lamports = self
+            .escrowed_lamports
+            .checked_add(lamports)
+            .ok_or_else(overflow)?;
+        self.claimable_clash = self
+            .claimable_clash
+            .checked_add(clash)
+            .ok_or_else(overflow)?;
+        self.rebate_weight = self
+            .rebate_weight
+            .checked_add(rebate_weight)
+            .ok_or_else(overflow)?;
+
+        Ok(())
+    }
+
+    pub fn add_purchase(
+        &mut self,
+        lamports: u64,
+        usd: u64,
+        clash: u64,
+        max_usd_per_wallet: u64,
+    ) -> ProgramResult {
+        let overflow = || ico_error(ICOError::PricingOverflow);
+
+        let usd_paid = self.usd_paid.checked_add(usd).ok_or_else(overflow)?;
+
+        if max_usd_per_wallet > 0 && usd_paid > max_usd_per_wallet {
+            msg!(format!(
+                "Wallet `{}` already bought {} micro USD, cap is {} micro USD",
+                self.buyer, self.usd_paid, max_usd_per_wallet
+            )
+            .as_str());
+
+            return Err(ico_error(ICOError::WalletCapExceeded));
+        }
+
+        self.lamports_paid = self
+            .lamports_paid
+            .checked_add(lamports)
+            .ok_or_else(overflow)?;
+        self.usd_paid = usd_paid;
+        self.clash_received = self
+            .clash_received
+            .checked_add(clash)
+            .ok_or_else(overflow)?;
+
+        Ok(())
+    }
+}
+
+// Batch auction bid, only its commitment is known until it is revealed
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Clone)]
+pub struct SealedBid {
+    pub commitment: [u8; 32],
+    pub price_lamports: u64,
+    pub quantity: u64,
+    pub revealed: bool,
+}
+
+impl SealedBid {
+    pub const LEN: usize = 32 + 8 + 8 + 1;
+
+    // Lamports owed for `quantity` tokens at `price_lamports` per whole token, rounded up
+    pub fn cost(quantity: u64, price_lamports: u64, decimals: u8) -> Result<u64, ProgramError> {
+        to_u64(mul_div(
+            quantity as u128,
+            price_lamports as u128,
+            token_unit(decimals)?,
+            Rounding::Up,
+        )?)
+    }
+}
+
+// Proof that a buyer belongs to the sale allowlist, leaves optionally commit to
+// the max value in micro USD the buyer is allowed to purchase
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+pub struct AllowlistProof {
+    pub allocation_usd: Option<u64>,
+    pub proof: Vec<[u8; 32]>,
 }
 
 #[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
 pub struct ClashTokenExchangeData {
     pub sol_as_lamports_amount: u64,
+
+    // Slippage bounds, exchange fails if fewer tokens would be received or
+    // if it executes after the expiry slot
+    pub min_clash_out: u64,
+    pub expiry_slot: Option<u64>,
+
+    // Required by sales restricted to an allowlist
+    pub allowlist_proof: Option<AllowlistProof>,
+
+    // Sells the tokens left and charges only their price when the sale cannot fill the
+    // whole exchange, instead of failing
+    pub allow_partial_fill: bool,
+}
+
+// Exchange data version 1, before the allowlist proof was added
+#[derive(BorshDeserialize)]
+struct ClashTokenExchangeDataV1 {
+    sol_as_lamports_amount: u64,
+    min_clash_out: u64,
+    expiry_slot: Option<u64>,
+}
+
+// Exchange data version 2, before partial fills were added
+#[derive(BorshDeserialize)]
+struct ClashTokenExchangeDataV2 {
+    sol_as_lamports_amount: u64,
+    min_clash_out: u64,
+    expiry_slot: Option<u64>,
+    allowlist_proof: Option<AllowlistProof>,
+}
+
+impl ClashTokenExchangeData {
+    // Legacy exchange data only holds the lamports amount, newer data starts with a version tag
+    pub const LEGACY_LEN: usize = 8;
+    pub const VERSION_1: u8 = 1;
+    pub const VERSION_2: u8 = 2;
+    pub const VERSION: u8 = 3;
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() == Self::LEGACY_LEN {
+            return Ok(Self {
+                sol_as_lamports_amount: u64::try_from_slice(data)?,
+                min_clash_out: 0,
+                expiry_slot: None,
+                allowlist_proof: None,
+                allow_partial_fill: false,
+            });
+        }
+
+        match data.split_first() {
+            Some((&Self::VERSION_1, versioned_data)) => {
+                let data = ClashTokenExchangeDataV1::try_from_slice(versioned_data)?;
+
+                Ok(Self {
+                    sol_as_lamports_amount: data.sol_as_lamports_amount,
+                    min_clash_out: data.min_clash_out,
+                    expiry_slot: data.expiry_slot,
+                    allowlist_proof: None,
+                    allow_partial_fill: false,
+                })
+            }
+            Some((&Self::VERSION_2, versioned_data)) => {
+                let data = ClashTokenExchangeDataV2::try_from_slice(versioned_data)?;
+
+                Ok(Self {
+                    sol_as_lamports_amount: data.sol_as_lamports_amount,
+                    min_clash_out: data.min_clash_out,
+                    expiry_slot: data.expiry_slot,
+                    allowlist_proof: data.allowlist_proof,
+                    allow_partial_fill: false,
+                })
+            }
+            Some((&Self::VERSION, versioned_data)) => Ok(Self::try_from_slice(versioned_data)?),
+            _ => Err(ico_error(ICOError::InvalidExchangeDataVersion)),
+        }
+    }
+}
+
+// Exact output exchange, buyer pays whatever SOL the requested tokens cost up to a max
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct ClashTokenExactOutData {
+    pub clash_token_amount: u64,
+    pub max_lamports_in: u64,
+    pub expiry_slot: Option<u64>,
+
+    // Required by sales restricted to an allowlist
+    pub allowlist_proof: Option<AllowlistProof>,
+}
+
+// Exchange paid with an accepted SPL quote token instead of SOL
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct ClashTokenSPLExchangeData {
+    pub quote_amount: u64,
+    pub min_clash_out: u64,
+    pub expiry_slot: Option<u64>,
+
+    // Required by sales restricted to an allowlist
+    pub allowlist_proof: Option<AllowlistProof>,
+}
+
+// Amount bound by an exchange, either the payment sent or the tokens received
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExchangeOrder {
+    ExactIn {
+        amount_in: u64,
+        min_clash_out: u64,
+        allow_partial_fill: bool,
+    },
+    ExactOut {
+        clash_out: u64,
+        max_amount_in: u64,
+    },
+}
+
+// Currency used to pay an exchange
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PaymentCurrency {
+    Sol,
+    Spl,
+}
+
+// SOL committed to a fair launch
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct FairLaunchCommitData {
+    pub lamports: u64,
+}
+
+// Batch auction bid committing to `keccak256(bidder || price || quantity || salt)`, the
+// escrowed lamports must cover the bid once revealed
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct BidCommitData {
+    pub commitment: [u8; 32],
+    pub lamports: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct BidRevealData {
+    pub price_lamports: u64,
+    pub quantity: u64,
+    pub salt: [u8; 32],
 }
 
 #[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
 pub struct ClashTokenPaymentData {
     pub clash_token_amount: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::schedule::auction_rebate_weight;
+    use crate::util::bid_commitment;
+
+    fn buyer_record() -> BuyerRecord {
+        BuyerRecord {
+            sale: Pubkey::new_unique(),
+            buyer: Pubkey::new_unique(),
+            lamports_paid: 0,
+            usd_paid: 0,
+            clash_received: 0,
+            escrowed_lamports: 0,
+            claimable_clash: 0,
+            rebate_weight: 0,
+            committed_lamports: 0,
+            bid: None,
+            vesting_clash: 0,
+            released_clash: 0,
+        }
+    }
+
+    fn account_data(record: &BuyerRecord) -> Vec<u8> {
+        let mut data = vec![0; BuyerRecord::LEN];
+        record.serialize(&mut &mut data[..]).unwrap();
+        data
+    }
+
+    fn sale() -> ICOProgramData {
+        let mut ico_data = ICOProgramData::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            0,
+            Pubkey::new_unique(),
+            ICOSaleConfig {
+                clash_usd: 10_000,
+                sol_usd: 150_000_000,
+                price_source: SolPriceSource::Fixed,
+                min_usd_price: 0,
+                max_usd_price: u64::MAX,
+                clash_sol_wallet: Pubkey::new_unique(),
+                clash_payment_authority: Pubkey::new_unique(),
+                accepted_quote_mints: vec![],
+                max_usd_per_wallet: 0,
+                allowlist_root: None,
+                start_timestamp: 0,
+                end_timestamp: 1_000,
+                extension_window_seconds: 0,
+                guardian: None,
+                soft_cap_usd: 0,
+                hard_cap_usd: 0,
+                rounds: vec![],
+                bonding_curve: vec![],
+                dutch_auction: None,
+                fair_launch: None,
+                batch_auction: None,
+                vesting: None,
+                tge_timestamp: None,
+            },
+        );
+        ico_data.status = SaleStatus::Ended;
+        ico_data
+    }
+
+    // 1000 CLASH base units, bids from 100 lamports per whole token every 10 lamports
+    const BATCH_AUCTION: BatchAuction = BatchAuction {
+        supply: 1_000,
+        min_price_lamports: 100,
+        tick_lamports: 10,
+        reveal_end_timestamp: 2_000,
+    };
+
+    fn revealed_bid(price_lamports: u64, quantity: u64) -> SealedBid {
+        SealedBid {
+            commitment: [0; 32],
+            price_lamports,
+            quantity,
+            revealed: true,
+        }
+    }
+
+    // Batch auction cleared from the given revealed bids, with the fill of each bid
+    fn cleared_auction(bids: &[SealedBid]) -> (ICOProgramData, Vec<u64>) {
+        let mut ico_data = sale();
+        ico_data.config.batch_auction = Some(BATCH_AUCTION);
+
+        for bid in bids {
+            let tick = BATCH_AUCTION.price_tick(bid.price_lamports).unwrap();
+            ico_data.bid_demand[tick] += bid.quantity;
+        }
+
+        ico_data.clear_batch_auction().unwrap();
+
+        let fills = bids
+            .iter()
+            .map(|bid| ico_data.bid_fill(bid).unwrap())
+            .collect();
+
+        (ico_data, fills)
+    }
+
+    const VESTING: VestingSchedule = VestingSchedule {
+        tge_timestamp: 1_000,
+        tge_unlock_bps: 1_000,
+        cliff_seconds: 100,
+        duration_seconds: 1_000,
+    };
+
+    #[test]
+    fn dutch_auction_decays_to_floor() {
+        let auction = DutchAuction {
+            start_usd: 100_000,
+            floor_usd: 20_000,
+            duration_seconds: 1_000,
+            step_seconds: 100,
+            rebate: false,
+        };
+
+        assert_eq!(auction.price_at(1_000, 900), 100_000);
+        assert_eq!(auction.price_at(1_000, 1_150), 92_000);
+        assert_eq!(auction.price_at(1_000, 1_999), 28_000);
+        assert_eq!(auction.price_at(1_000, 2_000), 20_000);
+    }
+
+    #[test]
+    fn dutch_auction_with_floor_above_start_does_not_underflow() {
+        let auction = DutchAuction {
+            start_usd: 20_000,
+            floor_usd: 100_000,
+            duration_seconds: 1_000,
+            step_seconds: 0,
+            rebate: false,
+        };
+
+        assert_eq!(auction.price_at(1_000, 1_500), 20_000);
+        assert_eq!(auction.price_at(1_000, 2_000), 100_000);
+    }
+
+    #[test]
+    fn dutch_auction_rebates_never_owe_more_than_the_vault_holds() {
+        let mut ico_data = sale();
+        ico_data.config.dutch_auction = Some(DutchAuction {
+            start_usd: 100_000,
+            floor_usd: 20_000,
+            duration_seconds: 1_000,
+            step_seconds: 0,
+            rebate: true,
+        });
+
+        // Lamports paid at the auction price of each purchase, the last one clearing it
+        let purchases = [
+            (1_000_003, 100_000),
+            (777_777, 73_333),
+            (333_333, 51_111),
+            (1, 51_111),
+            (999_999, 20_001),
+        ];
+
+        let mut buyer_records = Vec::new();
+        let mut vault_lamports = 0u64;
+
+        for (lamports, clash_usd) in purchases {
+            let rebate_weight = auction_rebate_weight(lamports, clash_usd).unwrap();
+
+            let mut buyer_record = buyer_record();
+            buyer_record.add_escrow(lamports, 0, rebate_weight).unwrap();
+            ico_data.add_escrow(lamports, 0, rebate_weight).unwrap();
+            ico_data.record_auction_price(clash_usd);
+
+            buyer_records.push(buyer_record);
+            vault_lamports += lamports;
+        }
+
+        assert_eq!(ico_data.clearing_usd, 20_001);
+
+        // The vault releases floor(sum of weights * clearing) and each buyer keeps
+        // ceil(weight * clearing), so the lamports left always cover the next rebate
+        vault_lamports -= ico_data.finalize().unwrap();
+
+        for (buyer_record, (lamports, _)) in buyer_records.iter_mut().zip(purchases) {
+            let rebate_lamports = ico_data.release_rebate(buyer_record).unwrap();
+
+            assert!(rebate_lamports <= lamports);
+            assert_eq!(buyer_record.escrowed_lamports, 0);
+
+            vault_lamports = vault_lamports.checked_sub(rebate_lamports).unwrap();
+        }
+
+        // Only the rounding dust of each buyer is left in the vault
+        assert!(vault_lamports <= purchases.len() as u64);
+        assert_eq!(ico_data.escrowed_lamports, 0);
+        assert_eq!(ico_data.rebate_weight, 0);
+    }
+
+    #[test]
+    fn fair_launch_undersubscribed_sells_at_target_price() {
+        let fair_launch = FairLaunch {
+            allocation: 1_000_000,
+            raise_lamports: 10_000,
+            max_commit_lamports: 0,
+        };
+
+        // Commitments are filled in full at the price set by the SOL target
+        assert_eq!(fair_launch.settle(1_000, 4_000).unwrap(), (100_000, 1_000));
+        assert_eq!(fair_launch.settle(3_000, 4_000).unwrap(), (300_000, 3_000));
+        assert_eq!(fair_launch.raised_lamports(4_000), 4_000);
+    }
+
+    #[test]
+    fn fair_launch_oversubscribed_fills_pro_rata() {
+        let fair_launch = FairLaunch {
+            allocation: 1_000_000,
+            raise_lamports: 10_000,
+            max_commit_lamports: 0,
+        };
+
+        // A third of the commitments receives a third of the allocation, tokens rounded
+        // down and lamports spent rounded up
+        assert_eq!(
+            fair_launch.settle(10_000, 30_000).unwrap(),
+            (333_333, 3_334)
+        );
+        assert_eq!(
+            fair_launch.settle(20_000, 30_000).unwrap(),
+            (666_666, 6_667)
+        );
+        assert_eq!(fair_launch.raised_lamports(30_000), 10_000);
+    }
+
+    #[test]
+    fn fair_launch_settlement_never_owes_more_than_it_holds() {
+        let mut ico_data = sale();
+        ico_data.config.fair_launch = Some(FairLaunch {
+            allocation: 1_000,
+            raise_lamports: 997,
+            max_commit_lamports: 0,
+        });
+
+        let commitments = [333, 333, 334, 7, 993];
+        let committed_lamports: u64 = commitments.iter().sum();
+
+        for commitment in commitments {
+            ico_data.add_commitment(commitment, true).unwrap();
+        }
+
+        let mut refunded_lamports = 0;
+
+        for commitment in commitments {
+            let mut buyer_record = BuyerRecord {
+                committed_lamports: commitment,
+                ..buyer_record()
+            };
+            ico_data.settle_commitment(&mut buyer_record).unwrap();
+
+            assert_eq!(buyer_record.committed_lamports, 0);
+            assert_eq!(
+                buyer_record.lamports_paid + buyer_record.escrowed_lamports,
+                commitment
+            );
+
+            refunded_lamports += buyer_record.escrowed_lamports;
+        }
+
+        // Tokens sold stay within the allocation and the lamports left in the vault once the
+        // raise is released cover every refund
+        assert!(ico_data.clash_sold <= 1_000);
+        assert_eq!(ico_data.unsettled_lamports, 0);
+        assert_eq!(ico_data.escrowed_lamports, refunded_lamports);
+        assert!(refunded_lamports <= committed_lamports - 997);
+    }
+
+    #[test]
+    fn sale_without_hard_cap_has_no_remaining_usd() {
+        let mut ico_data = sale();
+        ico_data.status = SaleStatus::Active;
+
+        ico_data
+            .add_purchase(1_000, u64::MAX / 2, 10_000, true)
+            .unwrap();
+
+        assert_eq!(ico_data.remaining_usd(), None);
+        assert!(!ico_data.hard_cap_reached());
+        assert_eq!(ico_data.status, SaleStatus::Active);
+    }
+
+    #[test]
+    fn reaching_the_hard_cap_exactly_ends_the_sale() {
+        let mut ico_data = sale();
+        ico_data.status = SaleStatus::Active;
+        ico_data.config.hard_cap_usd = 100_000_000;
+
+        ico_data
+            .add_purchase(1_000, 60_000_000, 10_000, true)
+            .unwrap();
+
+        assert_eq!(ico_data.remaining_usd(), Some(40_000_000));
+        assert_eq!(ico_data.status, SaleStatus::Active);
+
+        ico_data
+            .add_purchase(1_000, 40_000_000, 10_000, true)
+            .unwrap();
+
+        assert_eq!(ico_data.remaining_usd(), Some(0));
+        assert!(ico_data.hard_cap_reached());
+        assert_eq!(ico_data.status, SaleStatus::Ended);
+        assert_eq!(ico_data.raised_usd, 100_000_000);
+        assert_eq!(ico_data.buyer_count, 2);
+    }
+
+    #[test]
+    fn hard_cap_left_below_the_minimum_purchase_ends_the_sale() {
+        let mut ico_data = sale();
+        ico_data.status = SaleStatus::Active;
+        ico_data.config.hard_cap_usd = 100_000_000;
+        ico_data.config.min_usd_price = 5_000_000;
+
+        ico_data
+            .add_purchase(1_000, 95_000_000, 10_000, true)
+            .unwrap();
+
+        assert_eq!(ico_data.remaining_usd(), Some(5_000_000));
+        assert!(!ico_data.hard_cap_reached());
+        assert_eq!(ico_data.status, SaleStatus::Active);
+
+        // The last fill rounds its value down, leaving less than anyone can buy
+        ico_data
+            .add_purchase(1_000, 4_999_999, 9_999, true)
+            .unwrap();
+
+        assert_eq!(ico_data.remaining_usd(), Some(1));
+        assert!(ico_data.hard_cap_reached());
+        assert_eq!(ico_data.status, SaleStatus::Ended);
+    }
+
+    #[test]
+    fn tge_sale_holds_tokens_until_claimed_after_the_tge() {
+        let mut ico_data = sale();
+
+        assert!(!ico_data.holds_tokens());
+
+        ico_data.config.tge_timestamp = Some(2_000);
+
+        assert!(ico_data.holds_tokens());
+
+        let mut buyer_record = buyer_record();
+        buyer_record.add_escrow(0, 10_000, 0).unwrap();
+        ico_data.add_escrow(0, 10_000, 0).unwrap();
+
+        assert_eq!(
+            ico_data
+                .release_claim(&mut buyer_record, 1_999)
+                .unwrap_err(),
+            ProgramError::Custom(ICOError::ClaimBeforeTGE as u32)
+        );
+        assert_eq!(buyer_record.claimable_clash, 10_000);
+        assert_eq!(ico_data.claimable_clash, 10_000);
+
+        assert_eq!(
+            ico_data.release_claim(&mut buyer_record, 2_000).unwrap(),
+            10_000
+        );
+        assert_eq!(ico_data.claimable_clash, 0);
+    }
+
+    // Soft cap sale of 100 USD having raised `raised_usd`, with two buyers escrowing lamports
+    // for claimable tokens
+    fn soft_cap_sale(raised_usd: u64) -> (ICOProgramData, [BuyerRecord; 2]) {
+        let mut ico_data = sale();
+        ico_data.config.soft_cap_usd = 100_000_000;
+        ico_data.raised_usd = raised_usd;
+
+        let mut buyer_records = [buyer_record(), buyer_record()];
+
+        for (buyer_record, (lamports, clash)) in buyer_records
+            .iter_mut()
+            .zip([(1_000, 10_000), (3_000, 30_000)])
+        {
+            buyer_record.add_escrow(lamports, clash, 0).unwrap();
+            ico_data.add_escrow(lamports, clash, 0).unwrap();
+        }
+
+        (ico_data, buyer_records)
+    }
+
+    #[test]
+    fn soft_cap_reached_finalize_releases_the_escrow() {
+        let (mut ico_data, _) = soft_cap_sale(100_000_000);
+
+        assert_eq!(ico_data.escrowed_lamports, 4_000);
+        assert_eq!(ico_data.finalize().unwrap(), 4_000);
+
+        // Tokens stay held for the buyers until they claim them
+        assert_eq!(ico_data.status, SaleStatus::Finalized);
+        assert_eq!(ico_data.escrowed_lamports, 0);
+        assert_eq!(ico_data.claimable_clash, 40_000);
+    }
+
+    #[test]
+    fn soft_cap_missed_refund_zeroes_buyer_escrow() {
+        let (mut ico_data, mut buyer_records) = soft_cap_sale(99_999_999);
+
+        assert_eq!(ico_data.finalize().unwrap(), 0);
+        assert_eq!(ico_data.status, SaleStatus::Refunding);
+        assert_eq!(ico_data.escrowed_lamports, 4_000);
+
+        assert_eq!(ico_data.refund_escrow(&mut buyer_records[0]), 1_000);
+        assert_eq!(buyer_records[0].escrowed_lamports, 0);
+        assert_eq!(buyer_records[0].claimable_clash, 0);
+        assert_eq!(ico_data.escrowed_lamports, 3_000);
+        assert_eq!(ico_data.claimable_clash, 30_000);
+
+        // A second refund of the same buyer sends nothing back
+        assert_eq!(ico_data.refund_escrow(&mut buyer_records[0]), 0);
+        assert_eq!(ico_data.escrowed_lamports, 3_000);
+    }
+
+    #[test]
+    fn claim_decrements_claimable_clash() {
+        let (mut ico_data, mut buyer_records) = soft_cap_sale(100_000_000);
+        ico_data.finalize().unwrap();
+
+        assert_eq!(
+            ico_data.release_claim(&mut buyer_records[1], 0).unwrap(),
+            30_000
+        );
+        assert_eq!(buyer_records[1].claimable_clash, 0);
+        assert_eq!(ico_data.claimable_clash, 10_000);
+
+        assert_eq!(ico_data.release_claim(&mut buyer_records[1], 0).unwrap(), 0);
+        assert_eq!(ico_data.claimable_clash, 10_000);
+    }
+
+    fn versioned_data(version: u8, payload: impl BorshSerialize) -> Vec<u8> {
+        let mut data = vec![version];
+        data.extend(payload.try_to_vec().unwrap());
+        data
+    }
+
+    fn exchange_data(
+        allowlist_proof: Option<AllowlistProof>,
+        allow_partial_fill: bool,
+    ) -> ClashTokenExchangeData {
+        ClashTokenExchangeData {
+            sol_as_lamports_amount: 5_000,
+            min_clash_out: 10,
+            expiry_slot: Some(99),
+            allowlist_proof,
+            allow_partial_fill,
+        }
+    }
+
+    #[test]
+    fn legacy_exchange_data_unpacks_the_lamports_amount() {
+        let data = ClashTokenExchangeData::unpack(&5_000u64.try_to_vec().unwrap()).unwrap();
+
+        assert_eq!(
+            data,
+            ClashTokenExchangeData {
+                min_clash_out: 0,
+                expiry_slot: None,
+                ..exchange_data(None, false)
+            }
+        );
+    }
+
+    #[test]
+    fn exchange_data_v1_and_v2_do_not_allow_partial_fills() {
+        let proof = AllowlistProof {
+            allocation_usd: Some(500),
+            proof: vec![[7; 32]],
+        };
+
+        let v1 = versioned_data(
+            ClashTokenExchangeData::VERSION_1,
+            (5_000u64, 10u64, Some(99u64)),
+        );
+        let v2 = versioned_data(
+            ClashTokenExchangeData::VERSION_2,
+            (5_000u64, 10u64, Some(99u64), Some(proof.clone())),
+        );
+
+        assert_eq!(
+            ClashTokenExchangeData::unpack(&v1).unwrap(),
+            exchange_data(None, false)
+        );
+        assert_eq!(
+            ClashTokenExchangeData::unpack(&v2).unwrap(),
+            exchange_data(Some(proof), false)
+        );
+    }
+
+    #[test]
+    fn exchange_data_v3_round_trips() {
+        for data in [exchange_data(None, true), exchange_data(None, false)] {
+            let packed = versioned_data(ClashTokenExchangeData::VERSION, &data);
+
+            assert_eq!(ClashTokenExchangeData::unpack(&packed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn exchange_data_with_unknown_version_is_rejected() {
+        let packed = versioned_data(4, exchange_data(None, true));
+
+        assert_eq!(
+            ClashTokenExchangeData::unpack(&packed).unwrap_err(),
+            ProgramError::Custom(ICOError::InvalidExchangeDataVersion as u32)
+        );
+    }
+
+    #[test]
+    fn sale_status_transitions() {
+        use SaleStatus::*;
+
+        let statuses = [
+            Pending, Active, Paused, Ended, Finalized, Refunding, Cancelled,
+        ];
+        let allowed = [
+            (Pending, Active),
+            (Pending, Paused),
+            (Pending, Cancelled),
+            (Active, Paused),
+            (Active, Ended),
+            (Active, Cancelled),
+            (Paused, Pending),
+            (Paused, Active),
+            (Paused, Ended),
+            (Paused, Cancelled),
+            (Ended, Finalized),
+            (Ended, Refunding),
+        ];
+
+        for from in statuses {
+            for to in statuses {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+
+        // Ended sales are settled, settled sales never change again
+        assert!(!Ended.can_transition_to(Cancelled));
+        assert!(statuses.iter().all(|to| !Finalized.can_transition_to(*to)
+            && !Refunding.can_transition_to(*to)
+            && !Cancelled.can_transition_to(*to)));
+    }
+
+    #[test]
+    fn sale_status_rejects_invalid_transition() {
+        let mut ico_data = sale();
+        ico_data.status = SaleStatus::Finalized;
+
+        assert_eq!(
+            ico_data.set_status(SaleStatus::Active).unwrap_err(),
+            ProgramError::Custom(ICOError::InvalidSaleStatusTransition as u32)
+        );
+        assert_eq!(ico_data.status, SaleStatus::Finalized);
+    }
+
+    #[test]
+    fn sale_status_follows_the_sale_window() {
+        let mut ico_data = sale();
+        ico_data.status = SaleStatus::Pending;
+        ico_data.config.start_timestamp = 100;
+
+        assert!(!ico_data.sync_status(99).unwrap());
+        assert_eq!(ico_data.status, SaleStatus::Pending);
+
+        assert!(ico_data.sync_status(100).unwrap());
+        assert_eq!(ico_data.status, SaleStatus::Active);
+
+        assert!(!ico_data.sync_status(999).unwrap());
+        assert!(ico_data.sync_status(1_000).unwrap());
+        assert_eq!(ico_data.status, SaleStatus::Ended);
+
+        // A sale paused at its end is ended too
+        ico_data.status = SaleStatus::Paused;
+
+        assert!(ico_data.sync_status(1_000).unwrap());
+        assert_eq!(ico_data.status, SaleStatus::Ended);
+
+        // A pending sale observed after its end goes through the active status
+        ico_data.status = SaleStatus::Pending;
+
+        assert!(ico_data.sync_status(1_000).unwrap());
+        assert_eq!(ico_data.status, SaleStatus::Ended);
+    }
+
+    #[test]
+    fn guardian_can_pause_but_not_resume() {
+        let mut ico_data = sale();
+        let guardian = Pubkey::new_unique();
+        ico_data.config.guardian = Some(guardian);
+
+        assert!(ico_data
+            .require_pause_authority(&ico_data.initializer, true)
+            .is_ok());
+        assert!(ico_data
+            .require_pause_authority(&ico_data.initializer, false)
+            .is_ok());
+        assert!(ico_data.require_pause_authority(&guardian, true).is_ok());
+        assert_eq!(
+            ico_data
+                .require_pause_authority(&guardian, false)
+                .unwrap_err(),
+            ProgramError::Custom(ICOError::InitializerAccountMismatch as u32)
+        );
+
+        let stranger = Pubkey::new_unique();
+
+        assert_eq!(
+            ico_data
+                .require_pause_authority(&stranger, true)
+                .unwrap_err(),
+            ProgramError::Custom(ICOError::InvalidPauseAuthority as u32)
+        );
+        assert_eq!(
+            ico_data
+                .require_pause_authority(&stranger, false)
+                .unwrap_err(),
+            ProgramError::Custom(ICOError::InitializerAccountMismatch as u32)
+        );
+    }
+
+    #[test]
+    fn purchase_in_extension_window_extends_the_sale_end() {
+        let mut config = sale().config;
+        config.extension_window_seconds = 100;
+
+        assert_eq!(config.extend_sale_window(950), Some(1_050));
+        assert_eq!(config.end_timestamp, 1_050);
+    }
+
+    #[test]
+    fn purchase_before_extension_window_keeps_the_sale_end() {
+        let mut config = sale().config;
+        config.extension_window_seconds = 100;
+
+        assert_eq!(config.extend_sale_window(500), None);
+        assert_eq!(config.extend_sale_window(900), None);
+        assert_eq!(config.end_timestamp, 1_000);
+    }
+
+    #[test]
+    fn sale_without_extension_window_never_extends() {
+        let mut config = sale().config;
+
+        assert_eq!(config.extend_sale_window(999), None);
+        assert_eq!(config.end_timestamp, 1_000);
+    }
+
+    #[test]
+    fn wallet_cap_rejects_purchases_crossing_it() {
+        let mut buyer_record = buyer_record();
+        buyer_record
+            .add_purchase(1_000, 60_000_000, 6_000, 100_000_000)
+            .unwrap();
+
+        let unchanged = account_data(&buyer_record);
+
+        assert_eq!(
+            buyer_record
+                .add_purchase(1_000, 40_000_001, 4_000, 100_000_000)
+                .unwrap_err(),
+            ProgramError::Custom(ICOError::WalletCapExceeded as u32)
+        );
+        assert_eq!(account_data(&buyer_record), unchanged);
+
+        // Reaching the cap exactly is allowed
+        buyer_record
+            .add_purchase(1_000, 40_000_000, 4_000, 100_000_000)
+            .unwrap();
+
+        assert_eq!(buyer_record.lamports_paid, 2_000);
+        assert_eq!(buyer_record.usd_paid, 100_000_000);
+        assert_eq!(buyer_record.clash_received, 10_000);
+    }
+
+    #[test]
+    fn wallet_without_cap_buys_without_limit() {
+        let mut buyer_record = buyer_record();
+
+        for _ in 0..3 {
+            buyer_record.add_purchase(0, u64::MAX / 4, 1, 0).unwrap();
+        }
+
+        assert_eq!(buyer_record.usd_paid, u64::MAX / 4 * 3);
+        assert_eq!(buyer_record.clash_received, 3);
+    }
+
+    #[test]
+    fn buyer_record_without_bid_unpacks_from_padded_account() {
+        let record = buyer_record();
+
+        assert_eq!(BuyerRecord::unpack(&account_data(&record)).unwrap(), record);
+    }
+
+    #[test]
+    fn buyer_record_with_bid_fills_account() {
+        let record = BuyerRecord {
+            bid: Some(SealedBid {
+                commitment: [7; 32],
+                price_lamports: 10,
+                quantity: 20,
+                revealed: true,
+            }),
+            ..buyer_record()
+        };
+
+        assert_eq!(record.try_to_vec().unwrap().len(), BuyerRecord::LEN);
+        assert_eq!(BuyerRecord::unpack(&account_data(&record)).unwrap(), record);
+    }
+
+    #[test]
+    fn buyer_record_unpacks_after_bid_is_settled() {
+        let bidder = BuyerRecord {
+            bid: Some(SealedBid {
+                commitment: [7; 32],
+                price_lamports: 10,
+                quantity: 20,
+                revealed: true,
+            }),
+            ..buyer_record()
+        };
+        let mut data = account_data(&bidder);
+
+        let settled = BuyerRecord {
+            bid: None,
+            ..bidder
+        };
+        settled.serialize(&mut &mut data[..]).unwrap();
+
+        assert_eq!(BuyerRecord::unpack(&data).unwrap(), settled);
+    }
+
+    #[test]
+    fn batch_auction_price_ticks() {
+        assert_eq!(BATCH_AUCTION.price_tick(100), Some(0));
+        assert_eq!(BATCH_AUCTION.price_tick(130), Some(3));
+        assert_eq!(BATCH_AUCTION.price_tick(410), Some(31));
+
+        // Below the min price, between two ticks or above the last tick
+        assert_eq!(BATCH_AUCTION.price_tick(90), None);
+        assert_eq!(BATCH_AUCTION.price_tick(105), None);
+        assert_eq!(BATCH_AUCTION.price_tick(420), None);
+    }
+
+    #[test]
+    fn batch_auction_undersubscribed_clears_at_lowest_bid() {
+        let (ico_data, fills) = cleared_auction(&[revealed_bid(120, 300), revealed_bid(100, 200)]);
+
+        assert_eq!(ico_data.clearing_lamports, 100);
+        assert_eq!(fills, vec![300, 200]);
+    }
+
+    #[test]
+    fn batch_auction_exactly_covered_clears_at_last_bid_needed() {
+        let (ico_data, fills) = cleared_auction(&[
+            revealed_bid(120, 600),
+            revealed_bid(110, 400),
+            revealed_bid(100, 500),
+        ]);
+
+        assert_eq!(ico_data.clearing_lamports, 110);
+        assert_eq!(ico_data.marginal_supply, 400);
+        assert_eq!(fills, vec![600, 400, 0]);
+    }
+
+    #[test]
+    fn batch_auction_oversubscribed_shares_marginal_tick_pro_rata() {
+        let (ico_data, fills) = cleared_auction(&[
+            revealed_bid(130, 400),
+            revealed_bid(110, 300),
+            revealed_bid(110, 300),
+            revealed_bid(110, 100),
+            revealed_bid(100, 500),
+        ]);
+
+        // 600 tokens are left for 700 demanded at the clearing price, shares round down
+        assert_eq!(ico_data.clearing_lamports, 110);
+        assert_eq!(ico_data.marginal_supply, 600);
+        assert_eq!(fills, vec![400, 257, 257, 85, 0]);
+        assert!(fills.iter().sum::<u64>() <= BATCH_AUCTION.supply);
+    }
+
+    #[test]
+    fn batch_auction_without_revealed_bids_refunds_every_bid() {
+        let (ico_data, _) = cleared_auction(&[]);
+
+        let unrevealed = SealedBid {
+            revealed: false,
+            ..revealed_bid(120, 300)
+        };
+
+        assert_eq!(ico_data.clearing_lamports, 0);
+        assert_eq!(ico_data.bid_fill(&unrevealed).unwrap(), 0);
+    }
+
+    #[test]
+    fn batch_auction_unrevealed_bid_wins_nothing() {
+        let (ico_data, fills) = cleared_auction(&[revealed_bid(120, 300)]);
+
+        let unrevealed = SealedBid {
+            revealed: false,
+            ..revealed_bid(130, 300)
+        };
+
+        assert_eq!(fills, vec![300]);
+        assert_eq!(ico_data.bid_fill(&unrevealed).unwrap(), 0);
+    }
+
+    #[test]
+    fn batch_auction_commitment_is_bound_to_the_bidder() {
+        let bidder = Pubkey::new_unique();
+        let salt = [7; 32];
+
+        let commitment = bid_commitment(&bidder, 110, 300, &salt);
+
+        assert_eq!(bid_commitment(&bidder, 110, 300, &salt), commitment);
+        assert_ne!(
+            bid_commitment(&Pubkey::new_unique(), 110, 300, &salt),
+            commitment
+        );
+        assert_ne!(bid_commitment(&bidder, 120, 300, &salt), commitment);
+    }
+
+    #[test]
+    fn vesting_before_tge_releases_nothing() {
+        assert_eq!(VESTING.vested(10_000, 0).unwrap(), 0);
+        assert_eq!(VESTING.vested(10_000, 999).unwrap(), 0);
+    }
+
+    #[test]
+    fn vesting_during_cliff_releases_tge_unlock() {
+        assert_eq!(VESTING.vested(10_000, 1_000).unwrap(), 1_000);
+        assert_eq!(VESTING.vested(10_000, 1_099).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn vesting_releases_linearly_after_cliff() {
+        assert_eq!(VESTING.vested(10_000, 1_100).unwrap(), 1_000);
+        assert_eq!(VESTING.vested(10_000, 1_600).unwrap(), 5_500);
+        assert_eq!(VESTING.vested(10_000, 1_601).unwrap(), 5_509);
+    }
+
+    #[test]
+    fn vesting_releases_total_once_over() {
+        assert_eq!(VESTING.vested(10_000, 2_100).unwrap(), 10_000);
+        assert_eq!(VESTING.vested(10_000, i64::MAX).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn vesting_without_duration_releases_total_after_cliff() {
+        let vesting = VestingSchedule {
+            duration_seconds: 0,
+            ..VESTING
+        };
+
+        assert_eq!(vesting.vested(10_000, 1_099).unwrap(), 1_000);
+        assert_eq!(vesting.vested(10_000, 1_100).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn vesting_with_huge_cliff_saturates() {
+        let vesting = VestingSchedule {
+            cliff_seconds: u64::MAX,
+            ..VESTING
+        };
+
+        assert_eq!(vesting.vested(10_000, i64::MAX - 1).unwrap(), 1_000);
+        assert_eq!(vesting.vested(10_000, i64::MAX).unwrap(), 1_000);
+    }
+}
@@ -1,10 +1,22 @@
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
+    account_info::AccountInfo, entrypoint::ProgramResult, keccak, msg, program_error::ProgramError,
     pubkey::Pubkey,
 };
 
 use spl_token::state::Account as TokenAccount;
 
+use crate::config::{
+    BUYER_RECORD_SEED, PRICE_FEED_SEED, PROGRAM_PDA_SEED1, PROGRAM_PDA_SEED2, VAULT_SEED,
+};
+
+use crate::error::{ico_err, ICOError};
+
+use crate::state::{
+    AllowlistProof, BatchAuction, ICOProgramData, ICOSaleConfig, SolPriceSource, VestingSchedule,
+};
+
+use std::fmt::Debug;
+
 // Helper function to avoid repeating code for account validation
 pub fn validate_account<'a>(
     acc_info: &AccountInfo,
@@ -70,3 +82,1033 @@ pub fn validate_token_account(
 
     Ok(())
 }
+
+// Helper function to derive the PDA holding data and tokens of a sale
+pub fn find_sale_pda(program_id: &Pubkey, sale_id: u64, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            &sale_id.to_le_bytes(),
+            mint.as_ref(),
+        ],
+        program_id,
+    )
+}
+
+// Helper function to derive the PDA holding the SOL/USD quotes of a publisher
+pub fn find_price_feed_pda(program_id: &Pubkey, publisher: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            PRICE_FEED_SEED,
+            publisher.as_ref(),
+        ],
+        program_id,
+    )
+}
+
+// Helper function to derive the PDA recording the purchases of a buyer in a sale
+pub fn find_buyer_record_pda(
+    program_id: &Pubkey,
+    sale_pda: &Pubkey,
+    buyer: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            BUYER_RECORD_SEED,
+            sale_pda.as_ref(),
+            buyer.as_ref(),
+        ],
+        program_id,
+    )
+}
+
+// Helper function to derive the PDA escrowing the SOL paid to a soft cap sale
+pub fn find_vault_pda(program_id: &Pubkey, sale_pda: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PROGRAM_PDA_SEED1,
+            PROGRAM_PDA_SEED2,
+            VAULT_SEED,
+            sale_pda.as_ref(),
+        ],
+        program_id,
+    )
+}
+
+// Allowlist leaves and nodes are hashed with distinct prefixes so a node can never be
+// presented as a leaf, node children are sorted so proofs do not carry their positions
+const ALLOWLIST_LEAF_PREFIX: &[u8] = &[0];
+const ALLOWLIST_NODE_PREFIX: &[u8] = &[1];
+
+// Helper function to compute the commitment of a batch auction bid, binding it to the bidder
+// so a commitment cannot be replayed by another wallet
+pub fn bid_commitment(
+    bidder: &Pubkey,
+    price_lamports: u64,
+    quantity: u64,
+    salt: &[u8; 32],
+) -> [u8; 32] {
+    keccak::hashv(&[
+        bidder.as_ref(),
+        &price_lamports.to_le_bytes(),
+        &quantity.to_le_bytes(),
+        salt,
+    ])
+    .to_bytes()
+}
+
+// Helper function to compute the allowlist leaf of a buyer, committing to its allocation if any
+pub fn allowlist_leaf(buyer: &Pubkey, allocation_usd: Option<u64>) -> [u8; 32] {
+    match allocation_usd {
+        Some(allocation_usd) => keccak::hashv(&[
+            ALLOWLIST_LEAF_PREFIX,
+            buyer.as_ref(),
+            &allocation_usd.to_le_bytes(),
+        ]),
+        None => keccak::hashv(&[ALLOWLIST_LEAF_PREFIX, buyer.as_ref()]),
+    }
+    .to_bytes()
+}
+
+// Helper function to check a buyer Merkle proof against the sale allowlist root. A zero
+// allocation would read as an uncapped wallet, so leaves committing to one are never valid
+pub fn verify_allowlist_proof(root: &[u8; 32], buyer: &Pubkey, proof: &AllowlistProof) -> bool {
+    if proof.allocation_usd == Some(0) {
+        return false;
+    }
+
+    let computed_root = proof.proof.iter().fold(
+        allowlist_leaf(buyer, proof.allocation_usd),
+        |node, sibling| {
+            let (left, right) = if node <= *sibling {
+                (node, *sibling)
+            } else {
+                (*sibling, node)
+            };
+
+            keccak::hashv(&[ALLOWLIST_NODE_PREFIX, &left, &right]).to_bytes()
+        },
+    );
+
+    &computed_root == root
+}
+
+// Helper function to reject purchases outside of the sale time window
+pub fn validate_sale_window(config: &ICOSaleConfig, now: i64) -> ProgramResult {
+    if now < config.start_timestamp {
+        msg!(format!(
+            "Sale starts at {}, current time is {}",
+            config.start_timestamp, now
+        )
+        .as_str());
+
+        ico_err(ICOError::SaleNotStarted)?;
+    }
+
+    if now >= config.end_timestamp {
+        msg!(format!(
+            "Sale ended at {}, current time is {}",
+            config.end_timestamp, now
+        )
+        .as_str());
+
+        ico_err(ICOError::SaleEnded)?;
+    }
+
+    Ok(())
+}
+
+// Helper function to check sale parameters before storing them on chain
+pub fn validate_sale_config(config: &ICOSaleConfig) -> ProgramResult {
+    if config.clash_usd == 0
+        && config.rounds.is_empty()
+        && config.bonding_curve.is_empty()
+        && config.dutch_auction.is_none()
+        && config.fair_launch.is_none()
+        && config.batch_auction.is_none()
+    {
+        ico_err(ICOError::InvalidConfigClashPrice)?;
+    }
+
+    if config.rounds.len() > ICOSaleConfig::MAX_ROUNDS {
+        ico_err(ICOError::InvalidConfigRounds)?;
+    }
+
+    for round in &config.rounds {
+        let invalid_window = matches!(
+            (round.start_timestamp, round.end_timestamp),
+            (Some(start), Some(end)) if start >= end
+        );
+
+        if round.clash_usd == 0 || round.allocation == 0 || invalid_window {
+            ico_err(ICOError::InvalidConfigRounds)?;
+        }
+    }
+
+    let curve = &config.bonding_curve;
+
+    if !curve.is_empty() {
+        let invalid_points = curve.windows(2).any(|points| {
+            points[0].clash_sold >= points[1].clash_sold
+                || points[0].clash_usd > points[1].clash_usd
+        });
+
+        if curve.len() < 2
+            || curve.len() > ICOSaleConfig::MAX_CURVE_POINTS
+            || curve[0].clash_sold != 0
+            || curve[0].clash_usd == 0
+            || invalid_points
+            || !config.rounds.is_empty()
+        {
+            ico_err(ICOError::InvalidConfigBondingCurve)?;
+        }
+    }
+
+    if let Some(auction) = &config.dutch_auction {
+        if auction.floor_usd == 0
+            || auction.start_usd <= auction.floor_usd
+            || auction.duration_seconds == 0
+            || auction.step_seconds > auction.duration_seconds
+            || !config.rounds.is_empty()
+            || !config.bonding_curve.is_empty()
+        {
+            ico_err(ICOError::InvalidConfigDutchAuction)?;
+        }
+    }
+
+    if let Some(fair_launch) = &config.fair_launch {
+        if fair_launch.allocation == 0
+            || fair_launch.raise_lamports == 0
+            || config.allowlist_root.is_some()
+            || config.max_usd_per_wallet > 0
+            || config.soft_cap_usd > 0
+            || config.hard_cap_usd > 0
+            || !config.rounds.is_empty()
+            || !config.bonding_curve.is_empty()
+            || config.dutch_auction.is_some()
+        {
+            ico_err(ICOError::InvalidConfigFairLaunch)?;
+        }
+    }
+
+    if let Some(batch_auction) = &config.batch_auction {
+        let max_price = (batch_auction.tick_lamports as u128)
+            * (BatchAuction::MAX_PRICE_TICKS as u128 - 1)
+            + batch_auction.min_price_lamports as u128;
+
+        if batch_auction.supply == 0
+            || batch_auction.min_price_lamports == 0
+            || batch_auction.tick_lamports == 0
+            || max_price > u64::MAX as u128
+            || batch_auction.reveal_end_timestamp <= config.end_timestamp
+            || config.extension_window_seconds > 0
+            || config.allowlist_root.is_some()
+            || config.max_usd_per_wallet > 0
+            || config.soft_cap_usd > 0
+            || config.hard_cap_usd > 0
+            || !config.rounds.is_empty()
+            || !config.bonding_curve.is_empty()
+            || config.dutch_auction.is_some()
+            || config.fair_launch.is_some()
+        {
+            ico_err(ICOError::InvalidConfigBatchAuction)?;
+        }
+    }
+
+    if let Some(vesting) = &config.vesting {
+        if vesting.tge_unlock_bps > VestingSchedule::MAX_BPS
+            || config.soft_cap_usd > 0
+            || config.fair_launch.is_some()
+            || config.batch_auction.is_some()
+        {
+            ico_err(ICOError::InvalidConfigVesting)?;
+        }
+    }
+
+    if config.tge_timestamp.is_some()
+        && (config.vesting.is_some() || config.batch_auction.is_some())
+    {
+        ico_err(ICOError::InvalidConfigTGE)?;
+    }
+
+    match &config.price_source {
+        SolPriceSource::Fixed => {
+            if config.sol_usd == 0 {
+                ico_err(ICOError::InvalidConfigSolPrice)?;
+            }
+        }
+        SolPriceSource::Pyth {
+            price_account,
+            max_age_slots,
+            max_age_seconds,
+            max_confidence_bps,
+        } => {
+            if price_account == &Pubkey::default()
+                || (*max_age_slots == 0 && *max_age_seconds == 0)
+                || *max_confidence_bps == 0
+            {
+                ico_err(ICOError::InvalidConfigPriceSource)?;
+            }
+        }
+        SolPriceSource::PriceFeed {
+            publisher,
+            max_age_seconds,
+        } => {
+            if publisher == &Pubkey::default() || *max_age_seconds == 0 {
+                ico_err(ICOError::InvalidConfigPriceSource)?;
+            }
+        }
+    }
+
+    if config.min_usd_price >= config.max_usd_price {
+        ico_err(ICOError::InvalidConfigUsdLimits)?;
+    }
+
+    if config.clash_sol_wallet == Pubkey::default() {
+        ico_err(ICOError::InvalidConfigSolWallet)?;
+    }
+
+    if config.clash_payment_authority == Pubkey::default() {
+        ico_err(ICOError::InvalidConfigPaymentAuthority)?;
+    }
+
+    if config.guardian == Some(Pubkey::default()) {
+        ico_err(ICOError::InvalidConfigGuardian)?;
+    }
+
+    if config.max_usd_per_wallet > 0 && config.max_usd_per_wallet < config.min_usd_price {
+        ico_err(ICOError::InvalidConfigWalletCap)?;
+    }
+
+    if config.start_timestamp >= config.end_timestamp {
+        ico_err(ICOError::InvalidConfigSaleWindow)?;
+    }
+
+    if config.hard_cap_usd > 0
+        && (config.hard_cap_usd < config.soft_cap_usd || config.hard_cap_usd < config.min_usd_price)
+    {
+        ico_err(ICOError::InvalidConfigHardCap)?;
+    }
+
+    let quote_mints = &config.accepted_quote_mints;
+
+    if quote_mints.len() > ICOSaleConfig::MAX_QUOTE_MINTS {
+        ico_err(ICOError::InvalidConfigQuoteMints)?;
+    }
+
+    for (index, quote_mint) in quote_mints.iter().enumerate() {
+        if quote_mint.mint == Pubkey::default()
+            || quote_mint.usd_price == 0
+            || quote_mints[..index]
+                .iter()
+                .any(|previous| previous.mint == quote_mint.mint)
+        {
+            ico_err(ICOError::InvalidConfigQuoteMints)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Helper function to reject updates of the sale config fields that escrowed, committed or
+// priced purchases depend on once the sale has buyers
+pub fn validate_sale_config_update(
+    ico_data: &ICOProgramData,
+    new: &ICOSaleConfig,
+) -> ProgramResult {
+    if !ico_data.has_buyers() {
+        return Ok(());
+    }
+
+    let old = &ico_data.config;
+
+    // A TGE can still be brought forward, buyers are never made to wait longer for their tokens
+    let tge_brought_forward = matches!(
+        (old.tge_timestamp, new.tge_timestamp),
+        (Some(old_tge), Some(new_tge)) if new_tge <= old_tge
+    );
+
+    let locked_changes = [
+        log_locked_field_change("soft_cap_usd", &old.soft_cap_usd, &new.soft_cap_usd),
+        log_locked_field_change("rounds", &old.rounds, &new.rounds),
+        log_locked_field_change("bonding_curve", &old.bonding_curve, &new.bonding_curve),
+        log_locked_field_change("dutch_auction", &old.dutch_auction, &new.dutch_auction),
+        // Dutch auction prices decay from the sale start, including the clearing price
+        old.dutch_auction.is_some()
+            && log_locked_field_change(
+                "start_timestamp",
+                &old.start_timestamp,
+                &new.start_timestamp,
+            ),
+        log_locked_field_change("fair_launch", &old.fair_launch, &new.fair_launch),
+        log_locked_field_change("batch_auction", &old.batch_auction, &new.batch_auction),
+        log_locked_field_change("vesting", &old.vesting, &new.vesting),
+        !tge_brought_forward
+            && log_locked_field_change("tge_timestamp", &old.tge_timestamp, &new.tge_timestamp),
+    ];
+
+    if locked_changes.iter().any(|changed| *changed) {
+        ico_err(ICOError::LockedConfigChange)?;
+    }
+
+    Ok(())
+}
+
+// Helper function to log a rejected update of a locked sale config field, returns whether
+// the value changed
+fn log_locked_field_change<T: PartialEq + Debug>(field: &str, old: &T, new: &T) -> bool {
+    if old == new {
+        return false;
+    }
+
+    msg!(format!(
+        "Sale config `{}` is locked once the sale has buyers: {:?} -> {:?}",
+        field, old, new
+    )
+    .as_str());
+
+    true
+}
+
+// Helper function to log a sale config field update, returns whether the value changed
+pub fn log_field_change<T: PartialEq + Debug>(field: &str, old: &T, new: &T) -> bool {
+    if old == new {
+        return false;
+    }
+
+    msg!(format!("Sale config `{}` changed: {:?} -> {:?}", field, old, new).as_str());
+
+    true
+}
+
+// Helper function to log every field that differs between two sale configs
+pub fn log_sale_config_changes(old: &ICOSaleConfig, new: &ICOSaleConfig) -> usize {
+    [
+        log_field_change("clash_usd", &old.clash_usd, &new.clash_usd),
+        log_field_change("sol_usd", &old.sol_usd, &new.sol_usd),
+        log_field_change("price_source", &old.price_source, &new.price_source),
+        log_field_change("min_usd_price", &old.min_usd_price, &new.min_usd_price),
+        log_field_change("max_usd_price", &old.max_usd_price, &new.max_usd_price),
+        log_field_change(
+            "clash_sol_wallet",
+            &old.clash_sol_wallet,
+            &new.clash_sol_wallet,
+        ),
+        log_field_change(
+            "clash_payment_authority",
+            &old.clash_payment_authority,
+            &new.clash_payment_authority,
+        ),
+        log_field_change(
+            "accepted_quote_mints",
+            &old.accepted_quote_mints,
+            &new.accepted_quote_mints,
+        ),
+        log_field_change(
+            "max_usd_per_wallet",
+            &old.max_usd_per_wallet,
+            &new.max_usd_per_wallet,
+        ),
+        log_field_change("allowlist_root", &old.allowlist_root, &new.allowlist_root),
+        log_field_change(
+            "start_timestamp",
+            &old.start_timestamp,
+            &new.start_timestamp,
+        ),
+        log_field_change("end_timestamp", &old.end_timestamp, &new.end_timestamp),
+        log_field_change(
+            "extension_window_seconds",
+            &old.extension_window_seconds,
+            &new.extension_window_seconds,
+        ),
+        log_field_change("guardian", &old.guardian, &new.guardian),
+        log_field_change("soft_cap_usd", &old.soft_cap_usd, &new.soft_cap_usd),
+        log_field_change("hard_cap_usd", &old.hard_cap_usd, &new.hard_cap_usd),
+        log_field_change("rounds", &old.rounds, &new.rounds),
+        log_field_change("bonding_curve", &old.bonding_curve, &new.bonding_curve),
+        log_field_change("dutch_auction", &old.dutch_auction, &new.dutch_auction),
+        log_field_change("fair_launch", &old.fair_launch, &new.fair_launch),
+        log_field_change("batch_auction", &old.batch_auction, &new.batch_auction),
+        log_field_change("vesting", &old.vesting, &new.vesting),
+        log_field_change("tge_timestamp", &old.tge_timestamp, &new.tge_timestamp),
+    ]
+    .iter()
+    .filter(|changed| **changed)
+    .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::state::{CurvePoint, DutchAuction, FairLaunch, PriceRound, QuoteMintConfig};
+
+    fn parent(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+        let (left, right) = if a <= b { (a, b) } else { (b, a) };
+
+        keccak::hashv(&[ALLOWLIST_NODE_PREFIX, left, right]).to_bytes()
+    }
+
+    fn proof(allocation_usd: Option<u64>, proof: Vec<[u8; 32]>) -> AllowlistProof {
+        AllowlistProof {
+            allocation_usd,
+            proof,
+        }
+    }
+
+    // Four buyers, two of them with an allocation: root = ((a, b), (c, d))
+    struct Allowlist {
+        buyers: [Pubkey; 4],
+        leaves: [[u8; 32]; 4],
+        root: [u8; 32],
+    }
+
+    fn allowlist() -> Allowlist {
+        let buyers = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let leaves = [
+            allowlist_leaf(&buyers[0], Some(500)),
+            allowlist_leaf(&buyers[1], None),
+            allowlist_leaf(&buyers[2], Some(1_000)),
+            allowlist_leaf(&buyers[3], None),
+        ];
+        let root = parent(
+            &parent(&leaves[0], &leaves[1]),
+            &parent(&leaves[2], &leaves[3]),
+        );
+
+        Allowlist {
+            buyers,
+            leaves,
+            root,
+        }
+    }
+
+    #[test]
+    fn allowlist_accepts_multi_level_proofs() {
+        let list = allowlist();
+        let ab = parent(&list.leaves[0], &list.leaves[1]);
+        let cd = parent(&list.leaves[2], &list.leaves[3]);
+
+        assert!(verify_allowlist_proof(
+            &list.root,
+            &list.buyers[0],
+            &proof(Some(500), vec![list.leaves[1], cd])
+        ));
+        assert!(verify_allowlist_proof(
+            &list.root,
+            &list.buyers[3],
+            &proof(None, vec![list.leaves[2], ab])
+        ));
+    }
+
+    #[test]
+    fn allowlist_rejects_proof_of_another_buyer() {
+        let list = allowlist();
+        let cd = parent(&list.leaves[2], &list.leaves[3]);
+
+        assert!(!verify_allowlist_proof(
+            &list.root,
+            &Pubkey::new_unique(),
+            &proof(Some(500), vec![list.leaves[1], cd])
+        ));
+        assert!(!verify_allowlist_proof(
+            &list.root,
+            &list.buyers[1],
+            &proof(Some(500), vec![list.leaves[1], cd])
+        ));
+    }
+
+    #[test]
+    fn allowlist_rejects_changed_allocation() {
+        let list = allowlist();
+        let cd = parent(&list.leaves[2], &list.leaves[3]);
+
+        assert!(!verify_allowlist_proof(
+            &list.root,
+            &list.buyers[0],
+            &proof(Some(501), vec![list.leaves[1], cd])
+        ));
+        assert!(!verify_allowlist_proof(
+            &list.root,
+            &list.buyers[0],
+            &proof(None, vec![list.leaves[1], cd])
+        ));
+    }
+
+    #[test]
+    fn allowlist_with_single_buyer_accepts_empty_proof() {
+        let buyer = Pubkey::new_unique();
+        let root = allowlist_leaf(&buyer, None);
+
+        assert!(verify_allowlist_proof(&root, &buyer, &proof(None, vec![])));
+        assert!(!verify_allowlist_proof(
+            &root,
+            &Pubkey::new_unique(),
+            &proof(None, vec![])
+        ));
+    }
+
+    #[test]
+    fn allowlist_rejects_zero_allocation() {
+        let buyer = Pubkey::new_unique();
+        let root = allowlist_leaf(&buyer, Some(0));
+
+        assert!(!verify_allowlist_proof(
+            &root,
+            &buyer,
+            &proof(Some(0), vec![])
+        ));
+    }
+
+    #[test]
+    fn allowlist_rejects_internal_node_as_leaf() {
+        let list = allowlist();
+        let ab = parent(&list.leaves[0], &list.leaves[1]);
+        let cd = parent(&list.leaves[2], &list.leaves[3]);
+
+        // A node presented as a buyer hashes to a leaf, which differs from the node itself
+        assert!(!verify_allowlist_proof(
+            &list.root,
+            &Pubkey::new_from_array(ab),
+            &proof(None, vec![cd])
+        ));
+        assert_ne!(allowlist_leaf(&Pubkey::new_from_array(ab), None), ab);
+    }
+
+    // Fixed price sale accepted by `validate_sale_config`, each test below breaks one field
+    fn config() -> ICOSaleConfig {
+        ICOSaleConfig {
+            clash_usd: 10_000,
+            sol_usd: 150_000_000,
+            price_source: SolPriceSource::Fixed,
+            min_usd_price: 1_000_000,
+            max_usd_price: 100_000_000,
+            clash_sol_wallet: Pubkey::new_unique(),
+            clash_payment_authority: Pubkey::new_unique(),
+            accepted_quote_mints: vec![],
+            max_usd_per_wallet: 0,
+            allowlist_root: None,
+            start_timestamp: 0,
+            end_timestamp: 1_000,
+            extension_window_seconds: 0,
+            guardian: None,
+            soft_cap_usd: 0,
+            hard_cap_usd: 0,
+            rounds: vec![],
+            bonding_curve: vec![],
+            dutch_auction: None,
+            fair_launch: None,
+            batch_auction: None,
+            vesting: None,
+            tge_timestamp: None,
+        }
+    }
+
+    fn assert_config_rejected(config: &ICOSaleConfig, error: ICOError) {
+        assert_eq!(
+            validate_sale_config(config).unwrap_err(),
+            ProgramError::Custom(error as u32)
+        );
+    }
+
+    #[test]
+    fn sale_window_rejects_purchases_before_start_and_from_end() {
+        let config = ICOSaleConfig {
+            start_timestamp: 100,
+            ..config()
+        };
+
+        assert_eq!(
+            validate_sale_window(&config, 99).unwrap_err(),
+            ProgramError::Custom(ICOError::SaleNotStarted as u32)
+        );
+        assert!(validate_sale_window(&config, 100).is_ok());
+        assert!(validate_sale_window(&config, 999).is_ok());
+        assert_eq!(
+            validate_sale_window(&config, 1_000).unwrap_err(),
+            ProgramError::Custom(ICOError::SaleEnded as u32)
+        );
+    }
+
+    #[test]
+    fn config_fixture_is_valid() {
+        assert!(validate_sale_config(&config()).is_ok());
+    }
+
+    #[test]
+    fn config_requires_a_clash_price() {
+        let config = ICOSaleConfig {
+            clash_usd: 0,
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigClashPrice);
+    }
+
+    #[test]
+    fn config_requires_a_fixed_sol_price() {
+        let config = ICOSaleConfig {
+            sol_usd: 0,
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigSolPrice);
+    }
+
+    #[test]
+    fn config_requires_a_valid_price_source() {
+        let pyth = ICOSaleConfig {
+            price_source: SolPriceSource::Pyth {
+                price_account: Pubkey::default(),
+                max_age_slots: 10,
+                max_age_seconds: 0,
+                max_confidence_bps: 100,
+            },
+            ..config()
+        };
+        let price_feed = ICOSaleConfig {
+            price_source: SolPriceSource::PriceFeed {
+                publisher: Pubkey::new_unique(),
+                max_age_seconds: 0,
+            },
+            ..config()
+        };
+
+        assert_config_rejected(&pyth, ICOError::InvalidConfigPriceSource);
+        assert_config_rejected(&price_feed, ICOError::InvalidConfigPriceSource);
+    }
+
+    #[test]
+    fn config_requires_min_usd_below_max_usd() {
+        let config = ICOSaleConfig {
+            min_usd_price: 100_000_000,
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigUsdLimits);
+    }
+
+    #[test]
+    fn config_requires_a_sol_wallet() {
+        let config = ICOSaleConfig {
+            clash_sol_wallet: Pubkey::default(),
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigSolWallet);
+    }
+
+    #[test]
+    fn config_requires_a_payment_authority() {
+        let config = ICOSaleConfig {
+            clash_payment_authority: Pubkey::default(),
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigPaymentAuthority);
+    }
+
+    #[test]
+    fn config_requires_a_valid_guardian() {
+        let config = ICOSaleConfig {
+            guardian: Some(Pubkey::default()),
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigGuardian);
+    }
+
+    #[test]
+    fn config_requires_wallet_cap_above_min_usd() {
+        let config = ICOSaleConfig {
+            max_usd_per_wallet: 999_999,
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigWalletCap);
+    }
+
+    #[test]
+    fn config_requires_start_before_end() {
+        let config = ICOSaleConfig {
+            start_timestamp: 1_000,
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigSaleWindow);
+    }
+
+    #[test]
+    fn config_requires_hard_cap_above_soft_cap_and_min_usd() {
+        let below_soft_cap = ICOSaleConfig {
+            soft_cap_usd: 50_000_000,
+            hard_cap_usd: 49_999_999,
+            ..config()
+        };
+        let below_min_usd = ICOSaleConfig {
+            hard_cap_usd: 999_999,
+            ..config()
+        };
+
+        assert_config_rejected(&below_soft_cap, ICOError::InvalidConfigHardCap);
+        assert_config_rejected(&below_min_usd, ICOError::InvalidConfigHardCap);
+    }
+
+    #[test]
+    fn config_rejects_duplicate_quote_mints() {
+        let quote_mint = QuoteMintConfig {
+            mint: Pubkey::new_unique(),
+            usd_price: 1_000_000,
+        };
+        let config = ICOSaleConfig {
+            accepted_quote_mints: vec![quote_mint.clone(), quote_mint],
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigQuoteMints);
+    }
+
+    #[test]
+    fn config_rejects_empty_price_rounds() {
+        let config = ICOSaleConfig {
+            rounds: vec![PriceRound {
+                clash_usd: 10_000,
+                allocation: 0,
+                start_timestamp: None,
+                end_timestamp: None,
+            }],
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigRounds);
+    }
+
+    #[test]
+    fn config_rejects_decreasing_bonding_curve() {
+        let config = ICOSaleConfig {
+            bonding_curve: vec![
+                CurvePoint {
+                    clash_sold: 0,
+                    clash_usd: 20_000,
+                },
+                CurvePoint {
+                    clash_sold: 1_000,
+                    clash_usd: 10_000,
+                },
+            ],
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigBondingCurve);
+    }
+
+    #[test]
+    fn config_rejects_dutch_auction_starting_below_floor() {
+        let config = ICOSaleConfig {
+            dutch_auction: Some(DutchAuction {
+                start_usd: 10_000,
+                floor_usd: 20_000,
+                duration_seconds: 1_000,
+                step_seconds: 0,
+                rebate: false,
+            }),
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigDutchAuction);
+    }
+
+    #[test]
+    fn config_rejects_fair_launch_with_hard_cap() {
+        let config = ICOSaleConfig {
+            hard_cap_usd: 50_000_000,
+            fair_launch: Some(FairLaunch {
+                allocation: 1_000,
+                raise_lamports: 1_000,
+                max_commit_lamports: 0,
+            }),
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigFairLaunch);
+    }
+
+    #[test]
+    fn config_rejects_batch_auction_revealing_before_the_sale_end() {
+        let config = ICOSaleConfig {
+            batch_auction: Some(BatchAuction {
+                supply: 1_000,
+                min_price_lamports: 100,
+                tick_lamports: 10,
+                reveal_end_timestamp: 1_000,
+            }),
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigBatchAuction);
+    }
+
+    #[test]
+    fn config_rejects_vesting_unlocking_over_everything() {
+        let config = ICOSaleConfig {
+            vesting: Some(VestingSchedule {
+                tge_timestamp: 1_000,
+                tge_unlock_bps: VestingSchedule::MAX_BPS + 1,
+                cliff_seconds: 0,
+                duration_seconds: 1_000,
+            }),
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigVesting);
+    }
+
+    #[test]
+    fn config_rejects_tge_claims_with_vesting() {
+        let config = ICOSaleConfig {
+            vesting: Some(VestingSchedule {
+                tge_timestamp: 1_000,
+                tge_unlock_bps: 0,
+                cliff_seconds: 0,
+                duration_seconds: 1_000,
+            }),
+            tge_timestamp: Some(1_000),
+            ..config()
+        };
+
+        assert_config_rejected(&config, ICOError::InvalidConfigTGE);
+    }
+
+    // Pending sale without buyers, configured by `config`
+    fn sale() -> ICOProgramData {
+        ICOProgramData::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            0,
+            Pubkey::new_unique(),
+            config(),
+        )
+    }
+
+    fn locked_change_error() -> ProgramError {
+        ProgramError::Custom(ICOError::LockedConfigChange as u32)
+    }
+
+    #[test]
+    fn dutch_auction_start_is_locked_once_the_sale_has_buyers() {
+        let mut ico_data = sale();
+        ico_data.config.dutch_auction = Some(DutchAuction {
+            start_usd: 100_000,
+            floor_usd: 20_000,
+            duration_seconds: 1_000,
+            step_seconds: 0,
+            rebate: true,
+        });
+
+        let mut new = ico_data.config.clone();
+        new.start_timestamp = 100;
+
+        assert!(validate_sale_config_update(&ico_data, &new).is_ok());
+
+        ico_data.buyer_count = 1;
+
+        assert_eq!(
+            validate_sale_config_update(&ico_data, &new).unwrap_err(),
+            locked_change_error()
+        );
+
+        // Sales not priced from their start can still move it
+        ico_data.config.dutch_auction = None;
+        new.dutch_auction = None;
+
+        assert!(validate_sale_config_update(&ico_data, &new).is_ok());
+    }
+
+    #[test]
+    fn fair_launch_and_batch_auction_cannot_be_gated() {
+        let mut fair_launch = config();
+        fair_launch.fair_launch = Some(FairLaunch {
+            allocation: 1_000,
+            raise_lamports: 1_000,
+            max_commit_lamports: 0,
+        });
+
+        let mut batch_auction = config();
+        batch_auction.batch_auction = Some(BatchAuction {
+            supply: 1_000,
+            min_price_lamports: 100,
+            tick_lamports: 10,
+            reveal_end_timestamp: 2_000,
+        });
+
+        for (config, error) in [
+            (fair_launch, ICOError::InvalidConfigFairLaunch),
+            (batch_auction, ICOError::InvalidConfigBatchAuction),
+        ] {
+            assert!(validate_sale_config(&config).is_ok());
+
+            let gated = ICOSaleConfig {
+                allowlist_root: Some([7; 32]),
+                ..config.clone()
+            };
+            let wallet_capped = ICOSaleConfig {
+                max_usd_per_wallet: 1_000_000,
+                ..config
+            };
+
+            for config in [gated, wallet_capped] {
+                assert_eq!(
+                    validate_sale_config(&config).unwrap_err(),
+                    ProgramError::Custom(error as u32)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tge_can_only_be_brought_forward_once_the_sale_has_buyers() {
+        let mut ico_data = sale();
+        ico_data.config.tge_timestamp = Some(2_000);
+
+        let config = ico_data.config.clone();
+        let with_tge = |tge_timestamp| ICOSaleConfig {
+            tge_timestamp,
+            ..config.clone()
+        };
+
+        // Any change is allowed before the first purchase
+        assert!(validate_sale_config_update(&ico_data, &with_tge(Some(3_000))).is_ok());
+        assert!(validate_sale_config_update(&ico_data, &with_tge(None)).is_ok());
+
+        ico_data.buyer_count = 1;
+
+        assert!(validate_sale_config_update(&ico_data, &with_tge(Some(2_000))).is_ok());
+        assert!(validate_sale_config_update(&ico_data, &with_tge(Some(1_500))).is_ok());
+
+        // Delaying the TGE or dropping it, which transfers tokens on purchase, is rejected
+        for tge_timestamp in [Some(2_001), None] {
+            assert_eq!(
+                validate_sale_config_update(&ico_data, &with_tge(tge_timestamp)).unwrap_err(),
+                locked_change_error()
+            );
+        }
+
+        // A sale without a TGE cannot defer the tokens of its buyers either
+        ico_data.config.tge_timestamp = None;
+
+        assert_eq!(
+            validate_sale_config_update(&ico_data, &with_tge(Some(1_500))).unwrap_err(),
+            locked_change_error()
+        );
+    }
+}